@@ -0,0 +1,78 @@
+//! Compares [`Distortion`] with the per-pixel implementation it replaced.
+//!
+//! Run with `cargo bench --bench distortion`.
+
+use criterion::{BatchSize, BenchmarkId, Criterion, criterion_group, criterion_main};
+use dreamcore_image_processor::transformation::distortion::Distortion;
+use dreamcore_image_processor::transformation::{ImageTransformation, TransformContext};
+use image::{DynamicImage, GenericImage, GenericImageView, Rgba, RgbaImage};
+use rand::Rng;
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+use std::time::Duration;
+
+const SIZES: [u32; 3] = [512, 2048, 4096];
+
+fn background(size: u32) -> DynamicImage {
+    DynamicImage::ImageRgba8(RgbaImage::from_fn(size, size, |x, y| {
+        Rgba([(x % 256) as u8, (y % 256) as u8, ((x + y) % 256) as u8, 255])
+    }))
+}
+
+/// The original implementation, column-major `get_pixel`/`put_pixel` with three rng calls per pixel.
+fn naive_distortion(image: &mut DynamicImage, rng: &mut impl Rng, intensity: f32) {
+    let (width, height) = image.dimensions();
+
+    for x in 0..width {
+        for y in 0..height {
+            let channels = image.get_pixel(x, y).0;
+
+            let new_pixel = Rgba([
+                (channels[0] as f32 + rng.random_range(-15.0..15.0) * intensity).clamp(0.0, 255.0)
+                    as u8,
+                (channels[1] as f32 + rng.random_range(-15.0..15.0) * intensity).clamp(0.0, 255.0)
+                    as u8,
+                (channels[2] as f32 + rng.random_range(-15.0..15.0) * intensity).clamp(0.0, 255.0)
+                    as u8,
+                channels[3],
+            ]);
+
+            image.put_pixel(x, y, new_pixel);
+        }
+    }
+}
+
+fn bench_distortion(c: &mut Criterion) {
+    let mut group = c.benchmark_group("distortion");
+    group
+        .sample_size(10)
+        .measurement_time(Duration::from_secs(10));
+
+    let distortion = Distortion::new(1.8..2.0);
+
+    for size in SIZES {
+        let image = background(size);
+
+        group.bench_with_input(BenchmarkId::new("naive", size), &image, |b, image| {
+            let mut rng = StdRng::seed_from_u64(0);
+            b.iter_batched_ref(
+                || image.clone(),
+                |image| naive_distortion(image, &mut rng, 1.9),
+                BatchSize::LargeInput,
+            );
+        });
+
+        group.bench_with_input(BenchmarkId::new("fast", size), &image, |b, image| {
+            b.iter_batched_ref(
+                || (image.clone(), TransformContext::new(0)),
+                |(image, ctx)| distortion.transform(image, ctx).unwrap(),
+                BatchSize::LargeInput,
+            );
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_distortion);
+criterion_main!(benches);
@@ -0,0 +1,74 @@
+use crate::transformation::{Frame, ImageTransformation, TransformContext, TransformError};
+use image::DynamicImage;
+use log::info;
+use strum_macros::{Display, EnumString};
+
+#[derive(Debug, Clone, Copy, Default, EnumString, Display)]
+#[strum(serialize_all = "snake_case")]
+pub enum AnimationFormat {
+    #[default]
+    Gif,
+    Apng,
+}
+
+impl AnimationFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            AnimationFormat::Gif => "gif",
+            AnimationFormat::Apng => "png",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct AnimationOptions {
+    pub frames: u32,
+    pub fps: u16,
+    pub format: AnimationFormat,
+    /// Speed of the GIF palette quantization, from `1` (best colors) to `30` (fastest).
+    pub gif_speed: i32,
+}
+
+impl Default for AnimationOptions {
+    fn default() -> Self {
+        Self {
+            frames: 24,
+            fps: 12,
+            format: AnimationFormat::default(),
+            gif_speed: 10,
+        }
+    }
+}
+
+/// Renders every frame of a looping animation from the same background.
+///
+/// Each frame starts from a clone of `ctx`, so the steps make the same decisions in every
+/// frame and only vary what depends on [`Frame::time`] or [`TransformContext::motion_rng`].
+/// Returns the frames and the context of the first frame, which holds its records.
+pub fn render_frames(
+    transformation: &dyn ImageTransformation,
+    background: &DynamicImage,
+    ctx: &TransformContext,
+    frames: u32,
+) -> Result<(Vec<DynamicImage>, TransformContext), TransformError> {
+    let count = frames.max(1);
+    let mut rendered = Vec::with_capacity(count as usize);
+    let mut first = None;
+
+    for index in 0..count {
+        info!(
+            "Rendering frame {}/{count} of image {background:p}",
+            index + 1
+        );
+
+        let mut frame_ctx = ctx.clone().with_frame(Frame { index, count });
+        let mut image = background.clone();
+
+        transformation.transform(&mut image, &mut frame_ctx)?;
+
+        rendered.push(image);
+        first.get_or_insert(frame_ctx);
+    }
+
+    Ok((rendered, first.unwrap_or_else(|| ctx.clone())))
+}
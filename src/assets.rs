@@ -1,6 +1,249 @@
+use crate::weight::default_weight;
+use ab_glyph::FontArc;
+use image::DynamicImage;
 use include_dir::{Dir, include_dir};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::sync::{Arc, OnceLock};
+use thiserror::Error;
 
 pub static FONTS: &Dir = &include_dir!("$CARGO_MANIFEST_DIR/assets/fonts");
 
 pub static EYEBALLS: &Dir = &include_dir!("$CARGO_MANIFEST_DIR/assets/eyeballs");
 pub static WINGS: &Dir = &include_dir!("$CARGO_MANIFEST_DIR/assets/wings");
+
+/// Name of the optional manifest at the root of a pack.
+pub const PACK_MANIFEST: &str = "pack.toml";
+
+#[derive(Debug, Error)]
+pub enum AssetError {
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Invalid zip archive: {0}")]
+    Zip(#[from] zip::result::ZipError),
+
+    #[error("Invalid pack manifest: {0}")]
+    Manifest(#[from] toml::de::Error),
+
+    #[error("Invalid image {0}: {1}")]
+    Image(String, #[source] image::ImageError),
+
+    #[error("Invalid font {0}: {1}")]
+    Font(String, #[source] ab_glyph::InvalidFont),
+
+    #[error("Missing file {0} referenced by the pack manifest")]
+    MissingFile(String),
+}
+
+pub struct ImageAsset {
+    pub name: String,
+    pub image: DynamicImage,
+    pub weight: f32,
+    /// Point of the image, relative to its size, that other images are attached to,
+    /// e.g. where the eye sits on a wing. Defaults to the center.
+    pub anchor: Option<(f32, f32)>,
+}
+
+pub struct FontAsset {
+    pub name: String,
+    pub font: FontArc,
+    pub weight: f32,
+}
+
+/// Fonts and images used by the transformations.
+///
+/// A pack is a directory or a zip archive with the assets in `fonts/`, `eyeballs/` and `wings/`,
+/// or with a [`PACK_MANIFEST`] listing them along with their weights and anchors:
+///
+/// ```toml
+/// [[eyeballs]]
+/// file = "eyes/red.png"
+/// weight = 2.0
+///
+/// [[wings]]
+/// file = "wings/bat.png"
+/// anchor = [0.5, 0.4]
+/// ```
+#[derive(Default)]
+pub struct AssetPack {
+    pub fonts: Vec<FontAsset>,
+    pub eyeballs: Vec<ImageAsset>,
+    pub wings: Vec<ImageAsset>,
+}
+
+#[derive(Deserialize)]
+struct PackManifest {
+    #[serde(default)]
+    fonts: Vec<ManifestEntry>,
+    #[serde(default)]
+    eyeballs: Vec<ManifestEntry>,
+    #[serde(default)]
+    wings: Vec<ManifestEntry>,
+}
+
+#[derive(Deserialize)]
+struct ManifestEntry {
+    file: String,
+    #[serde(default = "default_weight")]
+    weight: f32,
+    anchor: Option<(f32, f32)>,
+}
+
+impl AssetPack {
+    /// The assets compiled into the binary.
+    pub fn embedded() -> Result<Self, AssetError> {
+        let mut files = BTreeMap::new();
+
+        for (category, dir) in [("fonts", FONTS), ("eyeballs", EYEBALLS), ("wings", WINGS)] {
+            for file in dir.files() {
+                let name = format!("{category}/{}", file.path().display());
+                files.insert(name, file.contents().to_vec());
+            }
+        }
+
+        Self::from_files(files)
+    }
+
+    /// The embedded assets, decoded on the first call and shared by every later one.
+    pub fn shared_embedded() -> Result<Arc<Self>, AssetError> {
+        static EMBEDDED: OnceLock<Arc<AssetPack>> = OnceLock::new();
+
+        if let Some(pack) = EMBEDDED.get() {
+            return Ok(pack.clone());
+        }
+
+        let pack = Arc::new(Self::embedded()?);
+        Ok(EMBEDDED.get_or_init(|| pack).clone())
+    }
+
+    /// Loads a pack from a directory or, if `path` is a file, from a zip archive.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, AssetError> {
+        let path = path.as_ref();
+
+        if path.is_dir() {
+            Self::from_dir(path)
+        } else {
+            Self::from_zip(path)
+        }
+    }
+
+    pub fn from_dir(path: impl AsRef<Path>) -> Result<Self, AssetError> {
+        let mut files = BTreeMap::new();
+        read_dir_files(path.as_ref(), path.as_ref(), &mut files)?;
+        Self::from_files(files)
+    }
+
+    pub fn from_zip(path: impl AsRef<Path>) -> Result<Self, AssetError> {
+        let mut archive = zip::ZipArchive::new(File::open(path)?)?;
+        let mut files = BTreeMap::new();
+
+        for i in 0..archive.len() {
+            let mut entry = archive.by_index(i)?;
+
+            if entry.is_dir() {
+                continue;
+            }
+
+            let mut contents = Vec::with_capacity(entry.size() as usize);
+            entry.read_to_end(&mut contents)?;
+            files.insert(entry.name().trim_start_matches("./").to_string(), contents);
+        }
+
+        Self::from_files(files)
+    }
+
+    /// Builds a pack from file contents keyed by their `/`-separated path inside the pack.
+    fn from_files(mut files: BTreeMap<String, Vec<u8>>) -> Result<Self, AssetError> {
+        let manifest = match files.remove(PACK_MANIFEST) {
+            Some(contents) => toml::from_str(&String::from_utf8_lossy(&contents))?,
+            None => {
+                let entries = |category: &str| {
+                    files
+                        .keys()
+                        .filter(|name| name.starts_with(&format!("{category}/")))
+                        .map(|name| ManifestEntry {
+                            file: name.clone(),
+                            weight: default_weight(),
+                            anchor: None,
+                        })
+                        .collect()
+                };
+
+                PackManifest {
+                    fonts: entries("fonts"),
+                    eyeballs: entries("eyeballs"),
+                    wings: entries("wings"),
+                }
+            }
+        };
+
+        let mut take = |entry: &ManifestEntry| {
+            files
+                .remove(&entry.file)
+                .ok_or_else(|| AssetError::MissingFile(entry.file.clone()))
+        };
+
+        let mut pack = AssetPack::default();
+
+        for entry in &manifest.fonts {
+            let font = FontArc::try_from_vec(take(entry)?)
+                .map_err(|err| AssetError::Font(entry.file.clone(), err))?;
+
+            pack.fonts.push(FontAsset {
+                name: entry.file.clone(),
+                font,
+                weight: entry.weight,
+            });
+        }
+
+        for (entries, out) in [
+            (&manifest.eyeballs, &mut pack.eyeballs),
+            (&manifest.wings, &mut pack.wings),
+        ] {
+            for entry in entries {
+                let image = image::load_from_memory(&take(entry)?)
+                    .map_err(|err| AssetError::Image(entry.file.clone(), err))?;
+
+                out.push(ImageAsset {
+                    name: entry.file.clone(),
+                    image,
+                    weight: entry.weight,
+                    anchor: entry.anchor,
+                });
+            }
+        }
+
+        Ok(pack)
+    }
+}
+
+fn read_dir_files(
+    root: &Path,
+    dir: &Path,
+    out: &mut BTreeMap<String, Vec<u8>>,
+) -> Result<(), AssetError> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+
+        if path.is_dir() {
+            read_dir_files(root, &path, out)?;
+            continue;
+        }
+
+        let name = path
+            .strip_prefix(root)
+            .unwrap_or(&path)
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+
+        out.insert(name, std::fs::read(&path)?);
+    }
+
+    Ok(())
+}
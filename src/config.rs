@@ -0,0 +1,92 @@
+use crate::provider::AnyProvider;
+use crate::transformation::Pipeline;
+use serde::Deserialize;
+use std::path::Path;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("Failed to read config: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Invalid TOML config: {0}")]
+    Toml(#[from] toml::de::Error),
+
+    #[error("Invalid JSON config: {0}")]
+    Json(#[from] serde_json::Error),
+
+    #[error("Unsupported config format: {0}")]
+    UnsupportedFormat(String),
+}
+
+/// Describes a whole run: where the backgrounds come from and which steps are applied to them.
+///
+/// ```toml
+/// [provider]
+/// type = "pinterest"
+/// query = "dreamcore landscape"
+///
+/// [[pipeline.steps]]
+/// type = "chance"
+/// probability = 0.6
+/// step = { type = "text", phrases = ["Exit?", { text = "Day {counter}", weight = 2.0 }] }
+///
+/// [[pipeline.steps]]
+/// type = "distortion"
+/// intensity = { start = 1.8, end = 2.0 }
+/// noise = { model = "film_grain", sigma = 10.0 }
+///
+/// [[pipeline.steps]]
+/// type = "one_of"
+/// choices = [
+///     { weight = 3.0, step = { type = "eyeballs", eyeball = "simple_eye", count = { start = 1, end = 3 } } },
+///     { weight = 1.0, step = { type = "eyeballs", eyeball = "eye_with_wings", count = { start = 1, end = 2 } } },
+/// ]
+///
+/// [[pipeline.steps]]
+/// type = "repeat"
+/// times = { start = 0, end = 2 }
+/// step = { type = "text", pack = "packs/handwriting.zip" }
+///
+/// [[pipeline.steps]]
+/// type = "eyeballs"
+/// eyeball = "simple_eye"
+/// count = { start = 2, end = 5 }
+/// placement = { spacing = 16, margin = 24, overlap_chance = 0.1 }
+/// ```
+///
+/// Text steps take their [`Phrases`](crate::transformation::text::phrases::Phrases) from
+/// `phrases`, `names` and a `phrases_file`, or use the built-in ones. Text and eyeballs steps
+/// use the embedded assets unless `pack` points to an
+/// [`AssetPack`](crate::assets::AssetPack) directory or zip. Both avoid the areas drawn on by
+/// earlier steps, tuned by their `placement`, see
+/// [`PlacementOptions`](crate::transformation::placement::PlacementOptions).
+///
+/// A Pinterest provider searches `query` and the `queries` next to it, a list of texts or of
+/// `{ query = "weirdcore hallway", weight = 2.0 }` tables, picking one by weight per background.
+/// It takes its credentials, headers and base URL from an optional `session` table, see
+/// [`PinterestSession`](crate::provider::pinterest::session::PinterestSession), and skips
+/// unusable search results according to an optional `filter` table, see
+/// [`PinterestFilter`](crate::provider::pinterest::filter::PinterestFilter). Its bookmarks and
+/// unused search results are saved to the file named by `state`, later runs continue from there.
+#[derive(Deserialize)]
+pub struct Config {
+    pub provider: Option<AnyProvider>,
+    pub pipeline: Pipeline,
+}
+
+impl Config {
+    /// Loads a config from a `.toml` or `.json` file, picking the format by its extension.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)?;
+
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("toml") => Ok(toml::from_str(&contents)?),
+            Some("json") => Ok(serde_json::from_str(&contents)?),
+            other => Err(ConfigError::UnsupportedFormat(
+                other.unwrap_or_default().to_string(),
+            )),
+        }
+    }
+}
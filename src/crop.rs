@@ -0,0 +1,234 @@
+use image::imageops::FilterType;
+use image::{DynamicImage, GenericImageView, GrayImage};
+use imageproc::gradients::{sobel_gradients, vertical_sobel};
+use rand::Rng;
+use rand::distr::weighted::WeightedIndex;
+use strum_macros::{Display, EnumString};
+
+/// How [`ResizeMode::Cover`](crate::ResizeMode::Cover) picks the window of the image it keeps.
+#[derive(Debug, Clone, Copy, Default, EnumString, Display)]
+#[strum(serialize_all = "snake_case")]
+pub enum CropMode {
+    /// A uniformly random window.
+    #[default]
+    Random,
+    /// The window in the middle of the image.
+    Center,
+    /// The window with the most going on, scored by edge density and entropy.
+    Saliency,
+    /// The window putting the strongest horizontal edge, usually the horizon, on one of its
+    /// thirds. Uses saliency to break ties and when the image can only be cropped sideways.
+    Horizon,
+}
+
+/// Whether the smart [`CropMode`]s keep the best window or sample one weighted by its score.
+#[derive(Debug, Clone, Copy, Default, EnumString, Display)]
+#[strum(serialize_all = "snake_case")]
+pub enum CropPick {
+    #[default]
+    Best,
+    Weighted,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Crop {
+    pub mode: CropMode,
+    pub pick: CropPick,
+}
+
+/// Longest side of the downscaled copy the windows are scored on.
+const ANALYSIS_SIZE: u32 = 128;
+/// Number of steps between candidate windows along each axis that can be cropped.
+const CANDIDATE_STEPS: u32 = 16;
+const HISTOGRAM_BINS: usize = 32;
+
+/// Top left corner of the `crop_width`x`crop_height` window of `img` to keep.
+pub fn choose_window(
+    img: &DynamicImage,
+    crop_width: u32,
+    crop_height: u32,
+    crop: Crop,
+    rng: &mut impl Rng,
+) -> (u32, u32) {
+    let (width, height) = img.dimensions();
+    let max_left = width.saturating_sub(crop_width);
+    let max_top = height.saturating_sub(crop_height);
+
+    match crop.mode {
+        CropMode::Random => (
+            rng.random_range(0..=max_left),
+            rng.random_range(0..=max_top),
+        ),
+        CropMode::Center => (max_left / 2, max_top / 2),
+        CropMode::Saliency | CropMode::Horizon => {
+            if max_left == 0 && max_top == 0 {
+                return (0, 0);
+            }
+
+            let steps = |max: u32| {
+                let count = if max > 0 { CANDIDATE_STEPS } else { 0 };
+                (0..=count).map(move |i| max as u64 * i as u64 / count.max(1) as u64)
+            };
+            let candidates = steps(max_left)
+                .flat_map(|left| steps(max_top).map(move |top| (left as u32, top as u32)))
+                .collect::<Vec<_>>();
+
+            let analysis = Analysis::new(img);
+            let scores = analysis.score(&candidates, crop_width, crop_height, crop.mode);
+
+            let index = match crop.pick {
+                CropPick::Best => scores
+                    .iter()
+                    .enumerate()
+                    .max_by(|(_, a), (_, b)| a.total_cmp(b))
+                    .map(|(i, _)| i)
+                    .unwrap_or(0),
+                CropPick::Weighted => WeightedIndex::new(scores.iter().map(|s| s + 1e-6))
+                    .map(|weights| rng.sample(weights))
+                    .unwrap_or_else(|_| rng.random_range(0..candidates.len())),
+            };
+
+            candidates[index]
+        }
+    }
+}
+
+/// Downscaled grayscale copy of the image with the maps the windows are scored on.
+struct Analysis {
+    gray: GrayImage,
+    /// Summed-area table of the gradient magnitude, one row and column larger than `gray`.
+    edges: Vec<u64>,
+    /// Row of the strongest horizontal edge.
+    horizon: u32,
+    scale: f32,
+}
+
+impl Analysis {
+    fn new(img: &DynamicImage) -> Self {
+        let (width, height) = img.dimensions();
+        let scale = (ANALYSIS_SIZE as f32 / width.max(height) as f32).min(1.0);
+        let gray = img
+            .resize_exact(
+                ((width as f32 * scale) as u32).max(1),
+                ((height as f32 * scale) as u32).max(1),
+                FilterType::Triangle,
+            )
+            .to_luma8();
+
+        let gradients = sobel_gradients(&gray);
+        let stride = gray.width() as usize + 1;
+        let mut edges = vec![0; stride * (gray.height() as usize + 1)];
+
+        for (x, y, pixel) in gradients.enumerate_pixels() {
+            let (x, y) = (x as usize, y as usize);
+            edges[(y + 1) * stride + x + 1] =
+                pixel.0[0] as u64 + edges[y * stride + x + 1] + edges[(y + 1) * stride + x]
+                    - edges[y * stride + x];
+        }
+
+        // Sobel responses on the outermost rows only see the image border
+        let vertical = vertical_sobel(&gray);
+        let horizon = (2..gray.height().saturating_sub(2))
+            .max_by_key(|&y| {
+                (0..gray.width())
+                    .map(|x| vertical.get_pixel(x, y).0[0].unsigned_abs() as u64)
+                    .sum::<u64>()
+            })
+            .unwrap_or(gray.height() / 2);
+
+        Self {
+            gray,
+            edges,
+            horizon,
+            scale,
+        }
+    }
+
+    fn edge_sum(&self, x0: u32, y0: u32, x1: u32, y1: u32) -> u64 {
+        let stride = self.gray.width() as usize + 1;
+        let at = |x: u32, y: u32| self.edges[y as usize * stride + x as usize];
+
+        at(x1, y1) + at(x0, y0) - at(x0, y1) - at(x1, y0)
+    }
+
+    fn entropy(&self, x0: u32, y0: u32, x1: u32, y1: u32) -> f32 {
+        let mut histogram = [0u32; HISTOGRAM_BINS];
+
+        for y in y0..y1 {
+            for x in x0..x1 {
+                let value = self.gray.get_pixel(x, y).0[0] as usize;
+                histogram[value * HISTOGRAM_BINS / 256] += 1;
+            }
+        }
+
+        let total = histogram.iter().sum::<u32>().max(1) as f32;
+        histogram
+            .iter()
+            .filter(|&&count| count > 0)
+            .map(|&count| {
+                let p = count as f32 / total;
+                -p * p.ln()
+            })
+            .sum()
+    }
+
+    /// Scores of the windows at `candidates`, higher is better and all of them are non-negative.
+    fn score(
+        &self,
+        candidates: &[(u32, u32)],
+        crop_width: u32,
+        crop_height: u32,
+        mode: CropMode,
+    ) -> Vec<f32> {
+        let (gray_width, gray_height) = self.gray.dimensions();
+        let to_gray = |v: u32, max: u32| ((v as f32 * self.scale) as u32).min(max);
+
+        let windows = candidates
+            .iter()
+            .map(|&(left, top)| {
+                let x0 = to_gray(left, gray_width - 1);
+                let y0 = to_gray(top, gray_height - 1);
+                let x1 = to_gray(left + crop_width, gray_width).max(x0 + 1);
+                let y1 = to_gray(top + crop_height, gray_height).max(y0 + 1);
+                (x0, y0, x1, y1)
+            })
+            .collect::<Vec<_>>();
+
+        let densities = windows
+            .iter()
+            .map(|&(x0, y0, x1, y1)| {
+                self.edge_sum(x0, y0, x1, y1) as f32 / ((x1 - x0) * (y1 - y0)) as f32
+            })
+            .collect::<Vec<_>>();
+        let entropies = windows
+            .iter()
+            .map(|&(x0, y0, x1, y1)| self.entropy(x0, y0, x1, y1))
+            .collect::<Vec<_>>();
+
+        let normalize = |values: &[f32], i: usize| {
+            let max = values.iter().copied().fold(0.0, f32::max);
+            if max > 0.0 { values[i] / max } else { 0.0 }
+        };
+        let saliency = |i: usize| (normalize(&densities, i) + normalize(&entropies, i)) / 2.0;
+
+        let vertical_freedom = candidates.iter().any(|&(_, top)| top > 0);
+
+        windows
+            .iter()
+            .enumerate()
+            .map(|(i, &(_, y0, _, y1))| match mode {
+                CropMode::Horizon if vertical_freedom => {
+                    let window_height = (y1 - y0) as f32;
+                    let horizon = self.horizon as f32 - y0 as f32;
+                    let distance = (horizon - window_height / 3.0)
+                        .abs()
+                        .min((horizon - window_height * 2.0 / 3.0).abs());
+                    let placement = (1.0 - 3.0 * distance / window_height).max(0.0);
+
+                    placement + saliency(i) * 0.25
+                }
+                _ => saliency(i),
+            })
+            .collect()
+    }
+}
@@ -1,11 +1,22 @@
+use crate::crop::Crop;
 use ab_glyph::{Font, Glyph, Point, ScaleFont, point};
-use image::imageops::{FilterType, resize};
-use image::{DynamicImage, GenericImageView};
-use rand::{Rng, rng};
+use image::imageops::{FilterType, overlay, resize};
+use image::{DynamicImage, GenericImageView, RgbaImage};
+use rand::Rng;
+use std::str::FromStr;
+use strum_macros::{Display, EnumString};
 
+pub mod animation;
 pub mod assets;
+pub mod config;
+pub mod crop;
+pub mod manifest;
+pub mod output;
 pub mod provider;
+#[cfg(feature = "server")]
+pub mod server;
 pub mod transformation;
+pub mod weight;
 
 /// Took from https://github.com/alexheretic/ab-glyph/blob/main/dev/src/layout.rs
 pub fn layout_paragraph<F, SF>(
@@ -48,40 +59,122 @@ pub fn layout_paragraph<F, SF>(
     }
 }
 
-pub fn crop_and_resize(img: &mut DynamicImage, final_size: u32) {
+/// Dimensions of an output image, parsed from `512` (a square) or `1080x1920`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Size {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl FromStr for Size {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse = |v: &str| match v.trim().parse::<u32>() {
+            Ok(0) => Err("size must be greater than zero".to_string()),
+            Ok(v) => Ok(v),
+            Err(err) => Err(format!("invalid size {v:?}: {err}")),
+        };
+
+        match s.split_once(['x', 'X']) {
+            Some((width, height)) => Ok(Size {
+                width: parse(width)?,
+                height: parse(height)?,
+            }),
+            None => {
+                let side = parse(s)?;
+                Ok(Size {
+                    width: side,
+                    height: side,
+                })
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for Size {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}x{}", self.width, self.height)
+    }
+}
+
+/// How [`resize_to`] fits an image into a target size with a different aspect ratio.
+#[derive(Debug, Clone, Copy, Default, EnumString, Display)]
+#[strum(serialize_all = "snake_case")]
+pub enum ResizeMode {
+    /// Fill the target completely, cropping a window of the target's aspect ratio chosen by
+    /// the [`Crop`].
+    #[default]
+    Cover,
+    /// Fit the whole image inside the target, centered on a transparent canvas.
+    Contain,
+    /// Scale both axes independently, distorting the aspect ratio.
+    Stretch,
+}
+
+pub fn resize_to(
+    img: &mut DynamicImage,
+    size: Size,
+    mode: ResizeMode,
+    crop: Crop,
+    rng: &mut impl Rng,
+) {
     let (width, height) = img.dimensions();
-    let crop_size = width.min(height);
+    let Size {
+        width: final_width,
+        height: final_height,
+    } = size;
 
-    let mut rng = rng();
-    let left = if width > crop_size {
-        rng.random_range(0..=width.saturating_sub(crop_size))
-    } else {
-        0
-    };
+    match mode {
+        ResizeMode::Cover => {
+            let (crop_width, crop_height) =
+                if width as u64 * final_height as u64 >= height as u64 * final_width as u64 {
+                    let w = height as u64 * final_width as u64 / final_height.max(1) as u64;
+                    (w.max(1) as u32, height)
+                } else {
+                    let h = width as u64 * final_height as u64 / final_width.max(1) as u64;
+                    (width, h.max(1) as u32)
+                };
 
-    let top = if height > crop_size {
-        rng.random_range(0..=height.saturating_sub(crop_size))
-    } else {
-        0
-    };
+            let (left, top) = crop::choose_window(img, crop_width, crop_height, crop, rng);
 
-    let right = left + crop_size;
-    let bottom = top + crop_size;
+            *img = img.crop(left, top, crop_width, crop_height);
 
-    *img = img.crop(left, top, right - left, bottom - top);
+            let resized = resize(img, final_width, final_height, FilterType::Lanczos3);
+            *img = DynamicImage::ImageRgba8(resized);
+        }
+        ResizeMode::Contain => {
+            let scale =
+                (final_width as f32 / width as f32).min(final_height as f32 / height as f32);
+            let fit_width = ((width as f32 * scale).round() as u32).clamp(1, final_width.max(1));
+            let fit_height = ((height as f32 * scale).round() as u32).clamp(1, final_height.max(1));
 
-    let resized = image::imageops::resize(img, final_size, final_size, FilterType::Lanczos3);
-    *img = DynamicImage::ImageRgba8(resized);
+            let resized = resize(img, fit_width, fit_height, FilterType::Lanczos3);
+            let mut canvas = RgbaImage::new(final_width, final_height);
+            overlay(
+                &mut canvas,
+                &resized,
+                (final_width.saturating_sub(fit_width) / 2) as _,
+                (final_height.saturating_sub(fit_height) / 2) as _,
+            );
+            *img = DynamicImage::ImageRgba8(canvas);
+        }
+        ResizeMode::Stretch => {
+            let resized = resize(img, final_width, final_height, FilterType::Lanczos3);
+            *img = DynamicImage::ImageRgba8(resized);
+        }
+    }
 }
 
+/// Resizes `img` so its width is `scale` of the background's shorter side, keeping its aspect ratio.
 fn resize_to_background_image_scale(
     img: &DynamicImage,
     background: &DynamicImage,
     scale: f32,
 ) -> DynamicImage {
-    let target_width = (background.width() as f32 * scale) as u32;
+    let target_width = ((background.width().min(background.height()) as f32 * scale) as u32).max(1);
     let aspect_ratio = img.height() as f32 / img.width() as f32;
-    let target_height = (target_width as f32 * aspect_ratio) as u32;
+    let target_height = ((target_width as f32 * aspect_ratio) as u32).max(1);
 
     DynamicImage::ImageRgba8(resize(
         img,
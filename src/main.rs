@@ -1,79 +1,718 @@
-use dreamcore_image_processor::crop_and_resize;
-use dreamcore_image_processor::provider::BackgroundProvider;
-use dreamcore_image_processor::provider::pinterest::PinterestProvider;
+use clap::{Args, Parser, Subcommand};
+use dreamcore_image_processor::animation::{AnimationFormat, AnimationOptions, render_frames};
+use dreamcore_image_processor::assets::AssetPack;
+use dreamcore_image_processor::config::Config;
+use dreamcore_image_processor::crop::{Crop, CropMode, CropPick};
+use dreamcore_image_processor::{ResizeMode, Size, resize_to};
+use dreamcore_image_processor::provider::cache::{BackgroundCache, CachedProvider};
+use dreamcore_image_processor::provider::dedup::{DedupOptions, DedupProvider};
+use dreamcore_image_processor::provider::directory::{DirectoryOptions, DirectoryProvider};
+use dreamcore_image_processor::provider::pinterest::{PinterestProvider, PinterestQuery};
+use dreamcore_image_processor::provider::pinterest::filter::PinterestFilter;
+use dreamcore_image_processor::provider::prefetch::{PrefetchOptions, PrefetchProvider};
+use dreamcore_image_processor::provider::retry::{RetryPolicy, RetryProvider};
+use dreamcore_image_processor::manifest::Manifest;
+use dreamcore_image_processor::output::{save_animation, save_with_manifest};
+use dreamcore_image_processor::provider::{AnyProvider, Background, BackgroundProvider};
+#[cfg(feature = "server")]
+use dreamcore_image_processor::server::{Server, ServerOptions};
 use dreamcore_image_processor::transformation::distortion::Distortion;
 use dreamcore_image_processor::transformation::eyes::{Eyeball, Eyeballs};
 use dreamcore_image_processor::transformation::text::DreamcoreStyledTextTransform;
-use dreamcore_image_processor::transformation::{ImageTransformation, Pipeline};
-use futures::future::join_all;
+use dreamcore_image_processor::transformation::text::phrases::Phrases;
+use dreamcore_image_processor::transformation::{
+    ImageTransformation, Pipeline, TransformContext, TransformError,
+};
+use futures::StreamExt;
+use futures::stream;
 use image::GenericImageView;
-use log::{error, info};
+use log::{error, info, warn};
+use std::path::PathBuf;
+use std::process::ExitCode;
 use std::sync::Arc;
-use std::time::{Duration, Instant};
+use std::time::Instant;
 use tokio::task::spawn_blocking;
-use tokio::time::sleep;
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    pretty_env_logger::init();
+#[derive(Parser)]
+#[command(version, about = "Turns ordinary backgrounds into dreamcore images")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Fetch backgrounds from a provider and transform them
+    Generate(GenerateArgs),
+    /// Transform local image files instead of fetching backgrounds
+    Apply(ApplyArgs),
+    /// Serve freshly generated images over HTTP
+    #[cfg(feature = "server")]
+    Serve(ServeArgs),
+}
+
+#[derive(Args)]
+struct PipelineArgs {
+    /// TOML or JSON file describing the provider and the pipeline
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+
+    /// Directory or zip with fonts, eyeballs and wings replacing the embedded ones,
+    /// configs set their own with the `pack` field of a step
+    #[arg(long, conflicts_with = "config")]
+    assets: Option<PathBuf>,
+
+    /// `.txt`, `.toml` or `.json` file with the phrases drawn by the default pipeline,
+    /// configs set their own with the `phrases_file` field of a text step
+    #[arg(long, conflicts_with = "config")]
+    phrases: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct FitArgs {
+    /// How backgrounds are fitted into `--size`: cover, contain or stretch
+    #[arg(long, default_value_t = ResizeMode::Cover)]
+    resize_mode: ResizeMode,
+
+    /// Which part of the background the cover resize mode keeps: random, center, saliency or
+    /// horizon
+    #[arg(long, default_value_t = CropMode::Random)]
+    crop: CropMode,
+
+    /// Whether saliency and horizon crops keep the best window or sample one by its score:
+    /// best or weighted
+    #[arg(long, default_value_t = CropPick::Best)]
+    crop_pick: CropPick,
+}
+
+#[derive(Args)]
+struct ProviderArgs {
+    /// Search query as `QUERY` or `QUERY=WEIGHT`, overrides the provider of the config. May be
+    /// repeated, every background is searched with a query picked by weight
+    #[arg(short, long, conflicts_with = "directory", value_parser = parse_query)]
+    query: Vec<PinterestQuery>,
+
+    /// Skip search results narrower than this many pixels, configs set their own with the
+    /// `filter` table of the provider
+    #[arg(long, default_value_t = 0, requires = "query")]
+    min_width: u32,
+
+    /// Skip search results lower than this many pixels
+    #[arg(long, default_value_t = 0, requires = "query")]
+    min_height: u32,
+
+    /// File the Pinterest bookmarks and unused search results are saved to, the next run
+    /// continues from it
+    #[arg(long, conflicts_with = "directory")]
+    state: Option<PathBuf>,
+
+    /// Take backgrounds from a local directory instead, overrides the provider of the config
+    #[arg(short, long)]
+    directory: Option<PathBuf>,
+
+    /// Also look for backgrounds in subdirectories of `--directory`
+    #[arg(short, long, requires = "directory")]
+    recursive: bool,
+
+    /// Directory caching the downloaded backgrounds, reused by later runs
+    #[arg(long)]
+    cache: Option<PathBuf>,
+
+    /// Size limit of `--cache` in megabytes, the least recently used backgrounds are evicted
+    #[arg(long, default_value_t = 1024, requires = "cache")]
+    cache_size: u64,
+
+    /// Only serve backgrounds from `--cache`, without touching the provider
+    #[arg(long, requires = "cache")]
+    offline: bool,
+
+    /// Skip backgrounds that were already used in this run, by URL and by looks
+    #[arg(long)]
+    dedup: bool,
+
+    /// File remembering the used backgrounds across runs, implies `--dedup`
+    #[arg(long)]
+    seen: Option<PathBuf>,
+
+    /// Backgrounds whose perceptual hashes differ in at most this many of 64 bits are
+    /// considered the same
+    #[arg(long, default_value_t = 4)]
+    dedup_distance: u32,
+
+    /// How many times fetching a background is attempted before giving up on it
+    #[arg(long, default_value_t = 5, value_parser = clap::value_parser!(u32).range(1..))]
+    max_attempts: u32,
+
+    /// How many backgrounds are downloaded ahead of time
+    #[arg(long, default_value_t = 8)]
+    prefetch: usize,
+
+    /// How many backgrounds are downloaded at the same time
+    #[arg(long, default_value_t = 4)]
+    prefetch_workers: usize,
+}
+
+#[derive(Args)]
+struct CommonArgs {
+    #[command(flatten)]
+    pipeline: PipelineArgs,
+
+    #[command(flatten)]
+    fit: FitArgs,
+
+    /// Directory the images are written to, created if missing
+    #[arg(short, long, default_value = "output")]
+    output_dir: PathBuf,
+
+    /// Seed of the first image, the following images use the next seeds
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// How many images are processed at the same time
+    #[arg(short = 'j', long, default_value_t = 8)]
+    concurrency: usize,
+
+    /// Also write the manifest of every image to a `.json` file next to it
+    #[arg(long)]
+    sidecar: bool,
+
+    /// Render a looping animation with this many frames instead of a still image
+    #[arg(long)]
+    frames: Option<u32>,
+
+    /// Frames per second of the animation
+    #[arg(long, default_value_t = 12, requires = "frames")]
+    fps: u16,
+
+    /// Animation format: gif or apng, the file extension is adjusted to match
+    #[arg(long, default_value_t = AnimationFormat::Gif, requires = "frames")]
+    animation_format: AnimationFormat,
+
+    /// GIF palette quantization speed, from 1 (best colors) to 30 (fastest)
+    #[arg(long, default_value_t = 10, value_parser = clap::value_parser!(i32).range(1..=30))]
+    gif_speed: i32,
+}
+
+#[derive(Args)]
+struct GenerateArgs {
+    #[command(flatten)]
+    common: CommonArgs,
 
-    let provider = Arc::new(PinterestProvider::new("dreamcore landscape"));
+    /// Number of images to generate
+    #[arg(short = 'n', long, default_value_t = 33)]
+    count: usize,
 
-    let pipeline = Pipeline::default()
-        + DreamcoreStyledTextTransform::default()
+    /// Output size in pixels, either `512` for a square or `WIDTHxHEIGHT`
+    #[arg(short, long, default_value = "512")]
+    size: Size,
+
+    #[command(flatten)]
+    provider: ProviderArgs,
+
+    /// Output file name, `{index}` and `{seed}` are substituted, one of them is required when
+    /// generating more than one image
+    #[arg(long, default_value = "image-{index}.png")]
+    filename: String,
+}
+
+#[derive(Args)]
+struct ApplyArgs {
+    #[command(flatten)]
+    common: CommonArgs,
+
+    /// Images to transform
+    #[arg(required = true)]
+    inputs: Vec<PathBuf>,
+
+    /// Resize the inputs before transforming, either `512` for a square or `WIDTHxHEIGHT`
+    #[arg(short, long)]
+    size: Option<Size>,
+
+    /// Output file name, `{index}`, `{seed}` and `{name}` are substituted
+    #[arg(long, default_value = "{name}-dreamcore.png")]
+    filename: String,
+}
+
+#[cfg(feature = "server")]
+#[derive(Args)]
+struct ServeArgs {
+    #[command(flatten)]
+    pipeline: PipelineArgs,
+
+    #[command(flatten)]
+    fit: FitArgs,
+
+    /// Address the server listens on
+    #[arg(short, long, default_value = "127.0.0.1:8080")]
+    bind: std::net::SocketAddr,
+
+    /// Size of generated images when the request doesn't set one
+    #[arg(short, long, default_value = "512")]
+    size: Size,
+
+    #[command(flatten)]
+    provider: ProviderArgs,
+
+    /// Additional pipeline selected with `?preset=NAME`, as `NAME=CONFIG`, may be repeated
+    #[arg(long = "preset", value_parser = parse_preset)]
+    presets: Vec<(String, PathBuf)>,
+
+    /// How many images are transformed at the same time
+    #[arg(short = 'j', long, default_value_t = 4)]
+    concurrency: usize,
+
+    /// Largest accepted upload in megabytes
+    #[arg(long, default_value_t = 32)]
+    max_upload: usize,
+
+    /// Largest size a request may ask for
+    #[arg(long, default_value = "4096")]
+    max_size: Size,
+}
+
+fn default_pipeline(pack: Arc<AssetPack>, phrases: Phrases) -> Result<Pipeline, TransformError> {
+    Ok(Pipeline::default()
+        + DreamcoreStyledTextTransform::with_pack(pack.clone())?.with_phrases(phrases)
         + Distortion::new(1.8..2.0)
-        + Eyeballs::new(Eyeball::SimpleEye, 1..=3)
-        + Eyeballs::new(Eyeball::EyeWithWings, 0..=2);
+        + Eyeballs::with_pack(Eyeball::SimpleEye, 1..=3, pack.clone())?
+        + Eyeballs::with_pack(Eyeball::EyeWithWings, 0..=2, pack)?)
+}
 
-    let pipeline = Arc::new(pipeline);
+fn load_config(
+    args: &PipelineArgs,
+) -> Result<(Option<AnyProvider>, Pipeline), Box<dyn std::error::Error>> {
+    if let Some(path) = &args.config {
+        info!("Loading config from {}", path.display());
+        let config = Config::load(path)?;
+        return Ok((config.provider, config.pipeline));
+    }
 
-    let now = Instant::now();
+    let pack = match &args.assets {
+        Some(path) => {
+            info!("Loading asset pack from {}", path.display());
+            Arc::new(AssetPack::load(path)?)
+        }
+        None => AssetPack::shared_embedded()?,
+    };
+
+    let phrases = match &args.phrases {
+        Some(path) => Phrases::load(path)?,
+        None => Phrases::default(),
+    };
+
+    Ok((None, default_pipeline(pack, phrases)?))
+}
+
+fn render_filename(template: &str, index: usize, seed: u64, name: &str) -> String {
+    template
+        .replace("{index}", &format!("{index:02}"))
+        .replace("{seed}", &seed.to_string())
+        .replace("{name}", name)
+}
+
+/// Rejects templates that would give every one of `count` images the same file name.
+fn check_filename(template: &str, count: usize, placeholders: &[&str]) -> Result<(), String> {
+    if count > 1 && !placeholders.iter().any(|p| template.contains(p)) {
+        return Err(format!(
+            "file name {template:?} has to contain one of {} to write {count} images",
+            placeholders.join(", ")
+        ));
+    }
+
+    Ok(())
+}
+
+fn context_for(seed: Option<u64>, index: usize) -> TransformContext {
+    match seed {
+        Some(seed) => TransformContext::new(seed.wrapping_add(index as u64)),
+        None => TransformContext::from_random_seed(),
+    }
+}
+
+/// Provider chosen by the command line flags, falling back to the one of the config,
+/// wrapped to retry failed fetches, cache backgrounds, skip duplicates and prefetch them.
+/// At most `limit` backgrounds are fetched, if given.
+fn select_provider(
+    args: ProviderArgs,
+    seed: Option<u64>,
+    configured: Option<AnyProvider>,
+    limit: Option<usize>,
+) -> Result<PrefetchProvider, Box<dyn std::error::Error>> {
+    let provider = match (args.directory.clone(), configured) {
+        _ if !args.query.is_empty() => {
+            let filter = PinterestFilter {
+                min_width: args.min_width,
+                min_height: args.min_height,
+                ..Default::default()
+            };
+            let provider = PinterestProvider::with_queries(args.query.clone())?;
+            AnyProvider::Pinterest(Box::new(provider.with_filter(filter)))
+        }
+        (Some(dir), _) => {
+            let options = DirectoryOptions {
+                recursive: args.recursive,
+                seed,
+                ..Default::default()
+            };
+            AnyProvider::Directory(Box::new(DirectoryProvider::new(dir, options)?))
+        }
+        (None, Some(provider)) => provider,
+        (None, None) => {
+            AnyProvider::Pinterest(Box::new(PinterestProvider::new("dreamcore landscape")))
+        }
+    };
 
-    let tasks = (0..33).map(|i| {
+    let provider = match (provider, &args.state) {
+        (AnyProvider::Pinterest(p), Some(path)) => {
+            AnyProvider::Pinterest(Box::new(p.with_checkpoint(path)))
+        }
+        (provider, _) => provider,
+    };
+
+    let policy = RetryPolicy {
+        max_attempts: args.max_attempts,
+        ..Default::default()
+    };
+    // Only downloads are worth caching, local files are as quick to read again
+    let cache = match (&provider, &args.cache) {
+        (AnyProvider::Pinterest(_), Some(dir)) => {
+            Arc::new(BackgroundCache::open(dir, args.cache_size * 1024 * 1024)?)
+        }
+        (_, Some(_)) => {
+            warn!("Not caching the backgrounds, --cache only applies to downloaded ones");
+            return prefetch(RetryProvider::new(provider, policy), &args, limit);
+        }
+        (_, None) => return prefetch(RetryProvider::new(provider, policy), &args, limit),
+    };
+    let provider = match provider {
+        AnyProvider::Pinterest(p) => AnyProvider::Pinterest(Box::new(p.with_cache(cache.clone()))),
+        provider => provider,
+    };
+    let provider =
+        CachedProvider::new(RetryProvider::new(provider, policy), cache).offline(args.offline);
+
+    prefetch(provider, &args, limit)
+}
+
+/// Puts the deduplication, if enabled, and the prefetching on top of the provider.
+fn prefetch<P>(
+    provider: P,
+    args: &ProviderArgs,
+    limit: Option<usize>,
+) -> Result<PrefetchProvider, Box<dyn std::error::Error>>
+where
+    P: BackgroundProvider + Send + Sync + 'static,
+{
+    let options = PrefetchOptions {
+        capacity: args.prefetch,
+        limit,
+        workers: args.prefetch_workers,
+        ..Default::default()
+    };
+
+    if !args.dedup && args.seen.is_none() {
+        return Ok(PrefetchProvider::new(provider, options));
+    }
+
+    let dedup = DedupOptions {
+        max_distance: args.dedup_distance,
+        path: args.seen.clone(),
+        ..Default::default()
+    };
+
+    Ok(PrefetchProvider::new(
+        DedupProvider::new(provider, dedup),
+        options,
+    ))
+}
+
+/// A query with an optional weight, `liminal pool=2` is picked twice as often as weight 1.
+fn parse_query(value: &str) -> Result<PinterestQuery, String> {
+    let (query, weight) = match value.rsplit_once('=') {
+        Some((query, weight)) => match weight.trim().parse::<f32>() {
+            Ok(weight) => (query, weight),
+            Err(_) => (value, 1.0),
+        },
+        None => (value, 1.0),
+    };
+
+    if query.trim().is_empty() {
+        return Err(format!("expected QUERY or QUERY=WEIGHT, got {value:?}"));
+    }
+    if !weight.is_finite() || weight < 0.0 {
+        return Err(format!("query weights must be non-negative, got {weight}"));
+    }
+
+    Ok(PinterestQuery::new(query.trim(), weight))
+}
+
+#[cfg(feature = "server")]
+fn parse_preset(value: &str) -> Result<(String, PathBuf), String> {
+    match value.split_once('=') {
+        Some((name, path)) if !name.is_empty() && !path.is_empty() => {
+            Ok((name.to_string(), PathBuf::from(path)))
+        }
+        _ => Err(format!("expected NAME=CONFIG, got {value:?}")),
+    }
+}
+
+impl FitArgs {
+    fn crop(&self) -> Crop {
+        Crop {
+            mode: self.crop,
+            pick: self.crop_pick,
+        }
+    }
+}
+
+impl CommonArgs {
+    fn animation(&self) -> Option<AnimationOptions> {
+        self.frames.map(|frames| AnimationOptions {
+            frames,
+            fps: self.fps,
+            format: self.animation_format,
+            gif_speed: self.gif_speed,
+        })
+    }
+}
+
+/// Transforms the background on the blocking pool and writes it to `path` along with its manifest.
+async fn transform_and_save(
+    pipeline: Arc<Pipeline>,
+    background: Background,
+    mut ctx: TransformContext,
+    path: PathBuf,
+    common: &CommonArgs,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let seed = ctx.seed();
+    let sidecar = common.sidecar;
+    let animation = common.animation();
+    let Background {
+        mut image, source, ..
+    } = background;
+
+    spawn_blocking(
+        move || -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+            match animation {
+                None => {
+                    pipeline.transform(&mut image, &mut ctx)?;
+                    info!("Saving {} (seed {seed})", path.display());
+                    let manifest = Manifest::new(&ctx, Some(source), &image);
+                    save_with_manifest(&image, &path, &manifest, sidecar)?;
+                }
+                Some(options) => {
+                    let (frames, ctx) =
+                        render_frames(pipeline.as_ref(), &image, &ctx, options.frames)?;
+                    let path = path.with_extension(options.format.extension());
+                    info!("Saving {} (seed {seed})", path.display());
+                    let manifest = Manifest::new(&ctx, Some(source), &image);
+                    save_animation(&frames, &path, &manifest, &options, sidecar)?;
+                }
+            }
+            Ok(())
+        },
+    )
+    .await??;
+
+    Ok(())
+}
+
+async fn generate(args: GenerateArgs) -> Result<usize, Box<dyn std::error::Error>> {
+    check_filename(&args.filename, args.count, &["{index}", "{seed}"])?;
+
+    let (provider, pipeline) = load_config(&args.common.pipeline)?;
+    // Prefetching more backgrounds than images are generated would only waste downloads
+    let provider = select_provider(args.provider, args.common.seed, provider, Some(args.count))?;
+
+    std::fs::create_dir_all(&args.common.output_dir)?;
+
+    let provider = Arc::new(provider);
+    let pipeline = Arc::new(pipeline);
+
+    let tasks = (0..args.count).map(|i| {
+        let common = &args.common;
         let pipeline = pipeline.clone();
         let provider = provider.clone();
+        let mut ctx = context_for(args.common.seed, i);
+        let path = args
+            .common
+            .output_dir
+            .join(render_filename(&args.filename, i, ctx.seed(), ""));
 
         async move {
-            let mut img = {
-                loop {
-                    match provider.fetch_background().await {
-                        Ok(img) => break img,
-                        Err(err) => {
-                            error!("Failed to fetch background image: {err}, sleeping for 1 second");
-                            sleep(Duration::from_secs(1)).await;
-                        }
-                    }
-                }
-            };
+            let mut background = provider
+                .fetch_background()
+                .await
+                .inspect_err(|err| error!("Failed to fetch a background for image {i}: {err}"))?;
 
-            let (w, h) = img.dimensions();
-            info!("Resizing image {i} from {w}x{h} to 512x512");
+            let (w, h) = background.image.dimensions();
+            info!("Resizing image {i} from {w}x{h} to {}", args.size);
 
-            crop_and_resize(&mut img, 512);
+            resize_to(
+                &mut background.image,
+                args.size,
+                common.fit.resize_mode,
+                common.fit.crop(),
+                &mut ctx.rng,
+            );
 
-            info!("Transforming image {i}");
+            info!("Transforming image {i} with seed {}", ctx.seed());
 
-            let img = spawn_blocking(move || {
-                pipeline.transform(&mut img);
-                img
-            })
-            .await
-            .unwrap();
+            let source = background.source.clone();
+            let saved = transform_and_save(pipeline, background, ctx, path, common)
+                .await
+                .inspect_err(|err| error!("Failed to generate image {i}: {err}"));
 
-            let path = format!("output/image-{i:02}.png");
-            info!("Saving {path}");
-            img.save(path).unwrap();
+            if saved.is_ok() {
+                provider.mark_used(&source).await;
+            }
+            saved
         }
     });
 
-    join_all(tasks).await;
+    // A task of its own, the images are resized on this one and may keep it busy for a while
+    let interrupt = tokio::spawn({
+        let provider = provider.clone();
+        async move {
+            if tokio::signal::ctrl_c().await.is_ok() {
+                warn!("Interrupted, saving the provider state");
+                provider.save_state().await;
+                std::process::exit(130);
+            }
+        }
+    });
 
-    let end = now.elapsed();
+    let failed = stream::iter(tasks)
+        .buffer_unordered(args.common.concurrency.max(1))
+        .filter(|res| std::future::ready(res.is_err()))
+        .count()
+        .await;
+    interrupt.abort();
 
-    println!(
-        "All transformations took {:0.2} seconds to complete",
-        end.as_secs_f32()
+    let stats = provider.stats();
+    info!(
+        "Prefetching: {} hits, {} waits, {} failed fetches",
+        stats.hits, stats.waits, stats.failures
     );
 
-    Ok(())
+    Ok(failed)
+}
+
+async fn apply(args: ApplyArgs) -> Result<usize, Box<dyn std::error::Error>> {
+    check_filename(
+        &args.filename,
+        args.inputs.len(),
+        &["{index}", "{seed}", "{name}"],
+    )?;
+
+    let (_, pipeline) = load_config(&args.common.pipeline)?;
+
+    std::fs::create_dir_all(&args.common.output_dir)?;
+
+    let pipeline = Arc::new(pipeline);
+
+    let tasks = args.inputs.iter().enumerate().map(|(i, input)| {
+        let common = &args.common;
+        let pipeline = pipeline.clone();
+        let mut ctx = context_for(args.common.seed, i);
+        let name = input
+            .file_stem()
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_else(|| format!("{i:02}"));
+        let path =
+            args.common
+                .output_dir
+                .join(render_filename(&args.filename, i, ctx.seed(), &name));
+
+        async move {
+            let result = async {
+                info!("Loading {}", input.display());
+                let mut image = image::open(input)?;
+
+                if let Some(size) = args.size {
+                    resize_to(
+                        &mut image,
+                        size,
+                        common.fit.resize_mode,
+                        common.fit.crop(),
+                        &mut ctx.rng,
+                    );
+                }
+
+                let background = Background::new(image, input.display().to_string());
+
+                transform_and_save(pipeline, background, ctx, path, common).await
+            };
+
+            result
+                .await
+                .inspect_err(|err| error!("Failed to transform {}: {err}", input.display()))
+        }
+    });
+
+    let failed = stream::iter(tasks)
+        .buffer_unordered(args.common.concurrency.max(1))
+        .filter(|res| std::future::ready(res.is_err()))
+        .count()
+        .await;
+
+    Ok(failed)
+}
+
+#[cfg(feature = "server")]
+async fn serve(args: ServeArgs) -> Result<usize, Box<dyn std::error::Error>> {
+    let (provider, pipeline) = load_config(&args.pipeline)?;
+    let provider = select_provider(args.provider, None, provider, None)?;
+
+    let options = ServerOptions {
+        size: args.size,
+        resize_mode: args.fit.resize_mode,
+        crop: args.fit.crop(),
+        concurrency: args.concurrency,
+        max_upload: args.max_upload * 1024 * 1024,
+        max_size: args.max_size,
+    };
+    let mut server = Server::new(pipeline, options).with_provider(provider);
+
+    for (name, path) in args.presets {
+        info!("Loading preset {name} from {}", path.display());
+        server = server.with_preset(name, Config::load(&path)?.pipeline);
+    }
+
+    server.serve(args.bind).await?;
+
+    Ok(0)
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    pretty_env_logger::init();
+
+    let cli = Cli::parse();
+    let now = Instant::now();
+
+    let result = match cli.command {
+        Command::Generate(args) => generate(args).await,
+        Command::Apply(args) => apply(args).await,
+        #[cfg(feature = "server")]
+        Command::Serve(args) => serve(args).await,
+    };
+
+    match result {
+        Ok(0) => {
+            println!(
+                "All transformations took {:0.2} seconds to complete",
+                now.elapsed().as_secs_f32()
+            );
+            ExitCode::SUCCESS
+        }
+        Ok(failed) => {
+            eprintln!("{failed} image(s) failed, see the log for details");
+            ExitCode::FAILURE
+        }
+        Err(err) => {
+            eprintln!("error: {err}");
+            ExitCode::from(2)
+        }
+    }
 }
@@ -0,0 +1,36 @@
+use crate::transformation::TransformContext;
+use image::DynamicImage;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// A decision made by a single step, e.g. the chosen distortion intensity or text position.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StepRecord {
+    pub step: String,
+    pub details: Value,
+}
+
+/// Everything needed to tell how an image was produced, embedded into every saved image.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Manifest {
+    pub generator: String,
+    pub seed: u64,
+    /// URL or path of the background the image was made from.
+    pub source: Option<String>,
+    pub width: u32,
+    pub height: u32,
+    pub steps: Vec<StepRecord>,
+}
+
+impl Manifest {
+    pub fn new(ctx: &TransformContext, source: Option<String>, image: &DynamicImage) -> Self {
+        Self {
+            generator: concat!(env!("CARGO_PKG_NAME"), " ", env!("CARGO_PKG_VERSION")).into(),
+            seed: ctx.seed(),
+            source,
+            width: image.width(),
+            height: image.height(),
+            steps: ctx.records().to_vec(),
+        }
+    }
+}
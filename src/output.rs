@@ -0,0 +1,176 @@
+use crate::animation::{AnimationFormat, AnimationOptions};
+use crate::manifest::Manifest;
+use image::codecs::gif::{GifEncoder, Repeat};
+use image::codecs::webp::WebPEncoder;
+use image::{Delay, DynamicImage, ExtendedColorType};
+use log::info;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use thiserror::Error;
+
+/// Keyword of the iTXt chunk holding the whole [`Manifest`] as JSON.
+pub const MANIFEST_KEYWORD: &str = "dreamcore:manifest";
+
+#[derive(Debug, Error)]
+pub enum SaveError {
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("PNG encoding error: {0}")]
+    Png(#[from] png::EncodingError),
+
+    #[error("Image encoding error: {0}")]
+    Image(#[from] image::ImageError),
+
+    #[error("Manifest serialization error: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+/// Saves the image, embedding the manifest as PNG text chunks when saving a `.png`.
+///
+/// With `sidecar` the manifest is also written next to the image as a `.json` file,
+/// which is the only way to keep it for formats other than PNG.
+pub fn save_with_manifest(
+    image: &DynamicImage,
+    path: &Path,
+    manifest: &Manifest,
+    sidecar: bool,
+) -> Result<(), SaveError> {
+    let is_png = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| e.eq_ignore_ascii_case("png"));
+
+    if is_png {
+        save_png(image, path, manifest)?;
+    } else {
+        image.save(path)?;
+    }
+
+    if sidecar {
+        save_sidecar(path, manifest)?;
+    }
+
+    Ok(())
+}
+
+/// Saves a looping animation, embedding the manifest into APNG files like [`save_with_manifest`].
+///
+/// GIF has no room for the manifest, use `sidecar` to keep it.
+pub fn save_animation(
+    frames: &[DynamicImage],
+    path: &Path,
+    manifest: &Manifest,
+    options: &AnimationOptions,
+    sidecar: bool,
+) -> Result<(), SaveError> {
+    let fps = options.fps.max(1);
+
+    match options.format {
+        AnimationFormat::Gif => {
+            let mut encoder = GifEncoder::new_with_speed(
+                BufWriter::new(File::create(path)?),
+                options.gif_speed.clamp(1, 30),
+            );
+            encoder.set_repeat(Repeat::Infinite)?;
+            encoder.encode_frames(frames.iter().map(|frame| {
+                image::Frame::from_parts(
+                    frame.to_rgba8(),
+                    0,
+                    0,
+                    Delay::from_numer_denom_ms(1000, fps as u32),
+                )
+            }))?;
+        }
+        AnimationFormat::Apng => {
+            let (width, height) = frames
+                .first()
+                .map(|frame| (frame.width(), frame.height()))
+                .unwrap_or_default();
+
+            let file = BufWriter::new(File::create(path)?);
+            let mut encoder = png_encoder(file, width, height, manifest)?;
+            encoder.set_animated(frames.len() as u32, 0)?;
+            encoder.set_frame_delay(1, fps)?;
+
+            let mut writer = encoder.write_header()?;
+            for frame in frames {
+                writer.write_image_data(&frame.to_rgba8())?;
+            }
+            writer.finish()?;
+        }
+    }
+
+    if sidecar {
+        save_sidecar(path, manifest)?;
+    }
+
+    Ok(())
+}
+
+fn save_sidecar(path: &Path, manifest: &Manifest) -> Result<(), SaveError> {
+    let sidecar_path = path.with_extension("json");
+    info!("Writing manifest {}", sidecar_path.display());
+    serde_json::to_writer_pretty(BufWriter::new(File::create(sidecar_path)?), manifest)?;
+
+    Ok(())
+}
+
+fn save_png(image: &DynamicImage, path: &Path, manifest: &Manifest) -> Result<(), SaveError> {
+    write_png(image, BufWriter::new(File::create(path)?), manifest)
+}
+
+/// Encodes the image as a PNG with the manifest embedded, like [`save_with_manifest`] does.
+pub fn encode_png(image: &DynamicImage, manifest: &Manifest) -> Result<Vec<u8>, SaveError> {
+    let mut bytes = Vec::new();
+    write_png(image, &mut bytes, manifest)?;
+
+    Ok(bytes)
+}
+
+/// Encodes the image as a lossless WebP, which has no room for the manifest.
+pub fn encode_webp(image: &DynamicImage) -> Result<Vec<u8>, SaveError> {
+    let rgba = image.to_rgba8();
+    let mut bytes = Vec::new();
+    WebPEncoder::new_lossless(&mut bytes).encode(
+        &rgba,
+        rgba.width(),
+        rgba.height(),
+        ExtendedColorType::Rgba8,
+    )?;
+
+    Ok(bytes)
+}
+
+fn write_png(image: &DynamicImage, out: impl Write, manifest: &Manifest) -> Result<(), SaveError> {
+    let rgba = image.to_rgba8();
+
+    let encoder = png_encoder(out, rgba.width(), rgba.height(), manifest)?;
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&rgba)?;
+    writer.finish()?;
+
+    Ok(())
+}
+
+/// An RGBA8 PNG encoder with the manifest already attached as text chunks.
+fn png_encoder<W: Write>(
+    out: W,
+    width: u32,
+    height: u32,
+    manifest: &Manifest,
+) -> Result<png::Encoder<'static, W>, SaveError> {
+    let mut encoder = png::Encoder::new(out, width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    encoder.add_text_chunk("Software".into(), manifest.generator.clone())?;
+    encoder.add_text_chunk("dreamcore:seed".into(), manifest.seed.to_string())?;
+    if let Some(source) = &manifest.source {
+        encoder.add_itxt_chunk("dreamcore:source".into(), source.clone())?;
+    }
+    encoder.add_itxt_chunk(MANIFEST_KEYWORD.into(), serde_json::to_string(manifest)?)?;
+
+    Ok(encoder)
+}
@@ -1,7 +1,21 @@
+use crate::provider::directory::DirectoryProvider;
+use crate::provider::pinterest::PinterestProvider;
+use derive_new::new;
 use image::DynamicImage;
+use reqwest::StatusCode;
+use reqwest::header::RETRY_AFTER;
+use serde::Deserialize;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
 use thiserror::Error;
 
+pub mod cache;
+pub mod dedup;
+pub mod directory;
+mod persist;
 pub mod pinterest;
+pub mod prefetch;
+pub mod retry;
 
 #[derive(Debug, Error)]
 pub enum FetchBackgroundError {
@@ -13,8 +27,174 @@ pub enum FetchBackgroundError {
 
     #[error("Invalid image: {0}")]
     InvalidImage(#[from] image::ImageError),
+
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Invalid session: {0}")]
+    Session(String),
+
+    #[error("HTTP error: {status}")]
+    Http {
+        status: StatusCode,
+        /// Parsed `Retry-After` header of the response.
+        retry_after: Option<Duration>,
+    },
+}
+
+/// How a [`RetryProvider`](retry::RetryProvider) reacts to a [`FetchBackgroundError`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorClass {
+    /// The source is unavailable for now, e.g. a timeout, a 5xx or a 429. Retried after
+    /// backing off.
+    Transient,
+    /// Only this image is broken, e.g. a dead URL or a file that can't be decoded. The next
+    /// fetch gets another image, so it's retried right away.
+    Skip,
+    /// Retrying can't help, e.g. when the provider ran out of images.
+    Fatal,
+}
+
+impl FetchBackgroundError {
+    pub fn class(&self) -> ErrorClass {
+        match self {
+            FetchBackgroundError::NoImages | FetchBackgroundError::Session(_) => ErrorClass::Fatal,
+            FetchBackgroundError::InvalidImage(_) | FetchBackgroundError::Io(_) => ErrorClass::Skip,
+            FetchBackgroundError::Http { status, .. } => status_class(*status),
+            FetchBackgroundError::NetworkError(err) => match err.status() {
+                Some(status) => status_class(status),
+                // Garbled bodies are as broken as undecodable images
+                None if err.is_decode() => ErrorClass::Skip,
+                None => ErrorClass::Transient,
+            },
+        }
+    }
+
+    /// How long the server asked to wait before the next request.
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            FetchBackgroundError::Http { retry_after, .. } => *retry_after,
+            _ => None,
+        }
+    }
+
+    /// Fails with [`FetchBackgroundError::Http`] unless the response is successful.
+    pub(crate) fn check_status(
+        response: reqwest::Response,
+    ) -> Result<reqwest::Response, FetchBackgroundError> {
+        let status = response.status();
+
+        if status.is_success() {
+            return Ok(response);
+        }
+
+        let retry_after = response
+            .headers()
+            .get(RETRY_AFTER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(parse_retry_after);
+
+        Err(FetchBackgroundError::Http {
+            status,
+            retry_after,
+        })
+    }
+}
+
+fn status_class(status: StatusCode) -> ErrorClass {
+    if status == StatusCode::TOO_MANY_REQUESTS
+        || status == StatusCode::REQUEST_TIMEOUT
+        || status.is_server_error()
+    {
+        ErrorClass::Transient
+    } else {
+        ErrorClass::Skip
+    }
+}
+
+/// `Retry-After` is either a number of seconds or an HTTP date.
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+
+    match value.parse::<u64>() {
+        Ok(seconds) => Some(Duration::from_secs(seconds)),
+        Err(_) => httpdate::parse_http_date(value)
+            .ok()
+            .map(|date| date.duration_since(SystemTime::now()).unwrap_or_default()),
+    }
+}
+
+/// A fetched background together with where it came from.
+#[derive(Debug, Clone, new)]
+pub struct Background {
+    pub image: DynamicImage,
+    /// URL or path of the original image.
+    #[new(into)]
+    pub source: String,
+    /// The file the image was decoded from, if the provider kept it, so it can be cached
+    /// without encoding it again.
+    #[new(default)]
+    pub encoded: Option<Arc<[u8]>>,
 }
 
 pub trait BackgroundProvider {
-    fn fetch_background(&self) -> impl Future<Output = Result<DynamicImage, FetchBackgroundError>>;
+    fn fetch_background(
+        &self,
+    ) -> impl Future<Output = Result<Background, FetchBackgroundError>> + Send;
+
+    /// Tells the provider the background of `source` was used, e.g. written to an output.
+    /// Backgrounds that were fetched but never used, e.g. prefetched ones, aren't marked.
+    fn mark_used(&self, _source: &str) -> impl Future<Output = ()> + Send {
+        async {}
+    }
+
+    /// Saves the state the provider keeps between runs right away, instead of when it's due or
+    /// dropped, e.g. before the run is interrupted.
+    fn save_state(&self) -> impl Future<Output = ()> + Send {
+        async {}
+    }
+
+    /// Whether every background is served once before any of them is served again, so coming
+    /// across a background twice means all of them were fetched in between.
+    fn cycles(&self) -> bool {
+        false
+    }
+}
+
+/// Any of the built-in providers, selected by the `type` key of a config file.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AnyProvider {
+    Pinterest(Box<PinterestProvider>),
+    Directory(Box<DirectoryProvider>),
+}
+
+impl BackgroundProvider for AnyProvider {
+    async fn fetch_background(&self) -> Result<Background, FetchBackgroundError> {
+        match self {
+            AnyProvider::Pinterest(p) => p.fetch_background().await,
+            AnyProvider::Directory(p) => p.fetch_background().await,
+        }
+    }
+
+    async fn mark_used(&self, source: &str) {
+        match self {
+            AnyProvider::Pinterest(p) => p.mark_used(source).await,
+            AnyProvider::Directory(p) => p.mark_used(source).await,
+        }
+    }
+
+    async fn save_state(&self) {
+        match self {
+            AnyProvider::Pinterest(p) => p.save_state().await,
+            AnyProvider::Directory(p) => p.save_state().await,
+        }
+    }
+
+    fn cycles(&self) -> bool {
+        match self {
+            AnyProvider::Pinterest(p) => p.cycles(),
+            AnyProvider::Directory(p) => p.cycles(),
+        }
+    }
 }
@@ -0,0 +1,535 @@
+use crate::provider::persist::AtomicFile;
+use crate::provider::{Background, BackgroundProvider, FetchBackgroundError};
+use image::ImageFormat;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+use tokio::task::spawn_blocking;
+
+const INDEX_FILE: &str = "index.json";
+
+/// How long changes of the index may stay unsaved, the changes made in between are saved
+/// together.
+const SAVE_INTERVAL: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Entry {
+    source: String,
+    /// Extension of the cached file, after the format the background was fetched in.
+    #[serde(default = "default_extension")]
+    extension: String,
+    bytes: u64,
+    /// Value of [`Index::clock`] when the entry was last read or written.
+    last_used: u64,
+}
+
+fn default_extension() -> String {
+    "png".to_string()
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Index {
+    clock: u64,
+    entries: HashMap<String, Entry>,
+    /// When the oldest change that wasn't saved yet was made.
+    #[serde(skip)]
+    changed: Option<Instant>,
+}
+
+impl Index {
+    fn total_bytes(&self) -> u64 {
+        self.entries.values().map(|entry| entry.bytes).sum()
+    }
+
+    fn touch(&mut self, key: &str) {
+        self.clock += 1;
+
+        if let Some(entry) = self.entries.get_mut(key) {
+            entry.last_used = self.clock;
+            self.change();
+        }
+    }
+
+    fn change(&mut self) {
+        self.changed.get_or_insert_with(Instant::now);
+    }
+}
+
+/// Backgrounds stored in a directory as the files they were fetched as, named after the hash
+/// of their source and evicted least recently used first once they take more than
+/// `max_bytes`.
+///
+/// The index of the entries is kept in an `index.json` next to them, so the cache survives
+/// between runs. Changes of the index are saved at most every [`SAVE_INTERVAL`] and when the
+/// cache is dropped.
+#[derive(Debug)]
+pub struct BackgroundCache {
+    dir: PathBuf,
+    max_bytes: u64,
+    index: Mutex<Index>,
+    file: AtomicFile,
+}
+
+impl BackgroundCache {
+    /// Opens the cache in `dir`, creating the directory if it's missing. A broken index is
+    /// replaced with an empty one, forgetting the cached files.
+    pub fn open(dir: impl Into<PathBuf>, max_bytes: u64) -> std::io::Result<Self> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)?;
+
+        let file = AtomicFile::new(dir.join(INDEX_FILE));
+        let index = match file.read()? {
+            Some(bytes) => serde_json::from_slice(&bytes).unwrap_or_else(|err| {
+                warn!(
+                    "Ignoring the broken cache index in {}: {err}",
+                    dir.display()
+                );
+                Index::default()
+            }),
+            None => Index::default(),
+        };
+        remove_unknown_files(&dir, &index)?;
+
+        info!(
+            "Opened background cache in {} with {} entries",
+            dir.display(),
+            index.entries.len()
+        );
+
+        Ok(Self {
+            dir,
+            max_bytes,
+            index: Mutex::new(index),
+            file,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.lock().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Sources of the cached backgrounds, least recently used first.
+    pub fn sources(&self) -> Vec<String> {
+        let index = self.lock();
+        let mut entries = index.entries.values().collect::<Vec<_>>();
+        entries.sort_by_key(|entry| entry.last_used);
+
+        entries.iter().map(|entry| entry.source.clone()).collect()
+    }
+
+    /// The cached background of `source`, if any. Entries whose file went missing or can't be
+    /// decoded anymore are dropped.
+    pub async fn get(&self, source: &str) -> Option<Background> {
+        let key = key(source);
+
+        let path = {
+            let mut index = self.lock();
+            let entry = index.entries.get(&key)?;
+            if entry.source != source {
+                return None;
+            }
+            let path = self.path(&key, &entry.extension);
+            index.touch(&key);
+            path
+        };
+        self.save_if_due().await;
+
+        let read = spawn_blocking(move || -> image::ImageResult<_> {
+            let bytes = std::fs::read(path)?;
+            let image = image::load_from_memory(&bytes)?;
+            Ok((image, bytes))
+        });
+        match read.await {
+            Ok(Ok((image, bytes))) => Some(Background {
+                image,
+                source: source.to_string(),
+                encoded: Some(bytes.into()),
+            }),
+            Ok(Err(err)) => {
+                warn!("Dropping cached background of {source}: {err}");
+                self.remove(&key).await;
+                None
+            }
+            Err(_) => None,
+        }
+    }
+
+    /// Stores the background, evicting the least recently used ones as needed. The file it was
+    /// fetched as is stored unchanged, backgrounds without one are stored as PNGs.
+    pub async fn insert(&self, background: &Background) -> std::io::Result<()> {
+        let key = key(&background.source);
+
+        let cached = {
+            let mut index = self.lock();
+            index.touch(&key);
+            index.entries.contains_key(&key)
+        };
+        if cached {
+            self.save_if_due().await;
+            return Ok(());
+        }
+
+        let encoded = background.encoded.clone().and_then(|encoded| {
+            let extension = image::guess_format(&encoded)
+                .ok()?
+                .extensions_str()
+                .first()?;
+            Some((encoded, *extension))
+        });
+        let extension = encoded.as_ref().map_or("png", |(_, extension)| *extension);
+
+        let path = self.path(&key, extension);
+        let image = background.image.clone();
+        let bytes = spawn_blocking(move || -> std::io::Result<u64> {
+            match encoded {
+                Some((encoded, _)) => std::fs::write(&path, encoded)?,
+                None => image
+                    .save_with_format(&path, ImageFormat::Png)
+                    .map_err(std::io::Error::other)?,
+            }
+
+            Ok(std::fs::metadata(path)?.len())
+        })
+        .await
+        .map_err(std::io::Error::other)??;
+
+        let evicted = {
+            let mut index = self.lock();
+            index.clock += 1;
+            let entry = Entry {
+                source: background.source.clone(),
+                extension: extension.to_string(),
+                bytes,
+                last_used: index.clock,
+            };
+            index.entries.insert(key.clone(), entry);
+            index.change();
+
+            let mut evicted = Vec::new();
+            while index.total_bytes() > self.max_bytes && index.entries.len() > 1 {
+                let Some(oldest) = index
+                    .entries
+                    .iter()
+                    .filter(|(k, _)| **k != key)
+                    .min_by_key(|(_, entry)| entry.last_used)
+                    .map(|(k, _)| k.clone())
+                else {
+                    break;
+                };
+
+                let entry = index.entries.remove(&oldest).expect("oldest entry exists");
+                info!("Evicting {} from the background cache", entry.source);
+                evicted.push(self.path(&oldest, &entry.extension));
+            }
+            evicted
+        };
+
+        remove_files(evicted).await;
+        self.save_if_due().await;
+
+        Ok(())
+    }
+
+    async fn remove(&self, key: &str) {
+        let path = {
+            let mut index = self.lock();
+            let Some(entry) = index.entries.remove(key) else {
+                return;
+            };
+            index.change();
+            self.path(key, &entry.extension)
+        };
+
+        remove_files(vec![path]).await;
+        self.save_if_due().await;
+    }
+
+    /// Saves the unsaved changes of the index right away.
+    pub async fn save(&self) {
+        self.save_older_than(Duration::ZERO).await;
+    }
+
+    /// Saves the index once its oldest unsaved change is [`SAVE_INTERVAL`] old.
+    async fn save_if_due(&self) {
+        self.save_older_than(SAVE_INTERVAL).await;
+    }
+
+    async fn save_older_than(&self, age: Duration) {
+        let snapshot = {
+            let mut index = self.lock();
+            match index.changed {
+                Some(changed) if changed.elapsed() >= age => {}
+                _ => return,
+            }
+            index.changed = None;
+            self.file.snapshot(&*index)
+        };
+
+        if let Err(err) = self.file.write(snapshot).await {
+            warn!("Failed to save the cache index: {err}");
+        }
+    }
+
+    fn path(&self, key: &str, extension: &str) -> PathBuf {
+        self.dir.join(format!("{key}.{extension}"))
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Index> {
+        self.index
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Drop for BackgroundCache {
+    fn drop(&mut self) {
+        let index = self
+            .index
+            .get_mut()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if index.changed.is_none() {
+            return;
+        }
+
+        if let Err(err) = self.file.write_blocking(&*index) {
+            warn!("Failed to save the cache index: {err}");
+        }
+    }
+}
+
+/// Name of the cache file of `source`, the 64 bit FNV-1a hash of it in hex.
+fn key(source: &str) -> String {
+    let hash = source.bytes().fold(0xCBF2_9CE4_8422_2325u64, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01B3)
+    });
+
+    format!("{hash:016x}")
+}
+
+/// Removes the cache files in `dir` the index doesn't know about, left behind by a run that
+/// stopped before saving its index. Files not named like cache files are left alone.
+fn remove_unknown_files(dir: &Path, index: &Index) -> std::io::Result<()> {
+    let known = index
+        .entries
+        .iter()
+        .map(|(key, entry)| format!("{key}.{}", entry.extension))
+        .collect::<HashSet<_>>();
+
+    for file in std::fs::read_dir(dir)? {
+        let file = file?;
+        let name = file.file_name();
+        let Some(name) = name.to_str() else {
+            continue;
+        };
+
+        let is_cache_file = match name.split_once('.') {
+            Some((key, _)) => key.len() == 16 && key.bytes().all(|b| b.is_ascii_hexdigit()),
+            None => false,
+        };
+        let is_index_tmp = name.starts_with(INDEX_FILE) && name.ends_with(".tmp");
+        if (is_cache_file || is_index_tmp) && !known.contains(name) {
+            info!("Removing {name} left in the background cache");
+            remove_file(&file.path());
+        }
+    }
+
+    Ok(())
+}
+
+async fn remove_files(paths: Vec<PathBuf>) {
+    if paths.is_empty() {
+        return;
+    }
+
+    let _ = spawn_blocking(move || paths.iter().for_each(|path| remove_file(path))).await;
+}
+
+fn remove_file(path: &Path) {
+    if let Err(err) = std::fs::remove_file(path)
+        && err.kind() != ErrorKind::NotFound
+    {
+        warn!("Failed to remove {}: {err}", path.display());
+    }
+}
+
+/// Stores every background of the wrapped provider in a [`BackgroundCache`].
+///
+/// In offline mode the wrapped provider isn't used at all, the cached backgrounds are served
+/// instead, least recently used first, starting over once all of them were served.
+#[derive(Debug)]
+pub struct CachedProvider<P> {
+    provider: P,
+    cache: Arc<BackgroundCache>,
+    offline: bool,
+    queue: tokio::sync::Mutex<VecDeque<String>>,
+}
+
+impl<P> CachedProvider<P> {
+    pub fn new(provider: P, cache: Arc<BackgroundCache>) -> Self {
+        Self {
+            provider,
+            cache,
+            offline: false,
+            queue: Default::default(),
+        }
+    }
+
+    pub fn offline(mut self, offline: bool) -> Self {
+        self.offline = offline;
+        self
+    }
+
+    async fn fetch_cached(&self) -> Result<Background, FetchBackgroundError> {
+        loop {
+            let source = {
+                let mut queue = self.queue.lock().await;
+                if queue.is_empty() {
+                    queue.extend(self.cache.sources());
+                }
+                queue.pop_front()
+            };
+
+            let Some(source) = source else {
+                return Err(FetchBackgroundError::NoImages);
+            };
+
+            if let Some(background) = self.cache.get(&source).await {
+                return Ok(background);
+            }
+        }
+    }
+}
+
+impl<P: BackgroundProvider + Sync> BackgroundProvider for CachedProvider<P> {
+    async fn fetch_background(&self) -> Result<Background, FetchBackgroundError> {
+        if self.offline {
+            return self.fetch_cached().await;
+        }
+
+        let background = self.provider.fetch_background().await?;
+
+        if let Err(err) = self.cache.insert(&background).await {
+            warn!("Failed to cache {}: {err}", background.source);
+        }
+
+        Ok(background)
+    }
+
+    async fn mark_used(&self, source: &str) {
+        self.provider.mark_used(source).await;
+    }
+
+    async fn save_state(&self) {
+        self.cache.save().await;
+        self.provider.save_state().await;
+    }
+
+    fn cycles(&self) -> bool {
+        self.offline || self.provider.cycles()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{DynamicImage, RgbImage};
+    use std::io::Cursor;
+
+    /// An empty directory for the cache of test `name`.
+    fn cache_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("cache-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    /// A background fetched as a PNG of [`png_bytes`] bytes.
+    fn background(source: &str) -> Background {
+        let image = DynamicImage::ImageRgb8(RgbImage::new(16, 16));
+        let mut png = Vec::new();
+        image
+            .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+            .unwrap();
+
+        Background {
+            image,
+            source: source.to_string(),
+            encoded: Some(png.into()),
+        }
+    }
+
+    fn png_bytes() -> u64 {
+        background("").encoded.unwrap().len() as u64
+    }
+
+    /// Fails the test when the cache isn't used instead.
+    struct Unreachable;
+
+    impl BackgroundProvider for Unreachable {
+        async fn fetch_background(&self) -> Result<Background, FetchBackgroundError> {
+            unreachable!("offline mode doesn't fetch")
+        }
+    }
+
+    #[tokio::test]
+    async fn least_recently_used_backgrounds_are_evicted() {
+        let dir = cache_dir("evict");
+        let cache = BackgroundCache::open(&dir, png_bytes() * 2).unwrap();
+
+        cache.insert(&background("a")).await.unwrap();
+        cache.insert(&background("b")).await.unwrap();
+        assert!(cache.get("a").await.is_some());
+        cache.insert(&background("c")).await.unwrap();
+
+        assert_eq!(cache.sources(), ["a", "c"]);
+        assert!(cache.get("b").await.is_none());
+        assert!(!cache.path(&key("b"), "png").exists());
+        drop(cache);
+
+        // The index was saved when the cache was dropped
+        let cache = BackgroundCache::open(&dir, png_bytes() * 2).unwrap();
+        assert_eq!(cache.sources(), ["a", "c"]);
+        drop(cache);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn offline_mode_serves_the_cache_over_and_over() {
+        let dir = cache_dir("offline");
+        let cache = Arc::new(BackgroundCache::open(&dir, u64::MAX).unwrap());
+        cache.insert(&background("a")).await.unwrap();
+        cache.insert(&background("b")).await.unwrap();
+
+        let provider = CachedProvider::new(Unreachable, cache).offline(true);
+        for expected in ["a", "b", "a"] {
+            let background = provider.fetch_background().await.unwrap();
+            assert_eq!(background.source, expected);
+        }
+        drop(provider);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn offline_mode_without_cached_backgrounds_has_no_images() {
+        let dir = cache_dir("empty");
+        let cache = Arc::new(BackgroundCache::open(&dir, u64::MAX).unwrap());
+
+        let provider = CachedProvider::new(Unreachable, cache).offline(true);
+        assert!(matches!(
+            provider.fetch_background().await,
+            Err(FetchBackgroundError::NoImages)
+        ));
+        drop(provider);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,408 @@
+use crate::provider::persist::AtomicFile;
+use crate::provider::{Background, BackgroundProvider, FetchBackgroundError};
+use image::DynamicImage;
+use image::imageops::FilterType;
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+use tokio::task::spawn_blocking;
+
+/// How long backgrounds marked as used may stay unsaved, the ones marked in between are saved
+/// together.
+const SAVE_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Perceptual hash telling apart backgrounds that are the same picture, even when re-encoded
+/// or resized.
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PerceptualHash {
+    /// Whether each pixel of an 8x8 thumbnail is brighter than the mean.
+    Average,
+    /// Whether each pixel of a 9x8 thumbnail is brighter than its right neighbour,
+    /// more robust against brightness and contrast changes.
+    #[default]
+    Difference,
+}
+
+impl PerceptualHash {
+    pub fn hash(self, image: &DynamicImage) -> u64 {
+        let bits = match self {
+            PerceptualHash::Average => {
+                let thumbnail = image.resize_exact(8, 8, FilterType::Triangle).to_luma8();
+                let mean = thumbnail.pixels().map(|p| p.0[0] as u32).sum::<u32>() / 64;
+                thumbnail
+                    .pixels()
+                    .map(|p| p.0[0] as u32 > mean)
+                    .collect::<Vec<_>>()
+            }
+            PerceptualHash::Difference => {
+                let thumbnail = image.resize_exact(9, 8, FilterType::Triangle).to_luma8();
+                (0..8)
+                    .flat_map(|y| (0..8).map(move |x| (x, y)))
+                    .map(|(x, y)| {
+                        thumbnail.get_pixel(x, y).0[0] > thumbnail.get_pixel(x + 1, y).0[0]
+                    })
+                    .collect()
+            }
+        };
+
+        bits.iter().fold(0, |hash, &bit| (hash << 1) | bit as u64)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DedupOptions {
+    pub hash: PerceptualHash,
+    /// Backgrounds whose hashes differ in at most this many bits count as the same.
+    pub max_distance: u32,
+    /// How many duplicates in a row are skipped before giving up with
+    /// [`FetchBackgroundError::NoImages`], the wrapped provider may serve nothing new anymore.
+    pub max_skips: u32,
+    /// File the seen set is loaded from and saved to, so later runs skip the backgrounds too.
+    pub path: Option<PathBuf>,
+}
+
+impl Default for DedupOptions {
+    fn default() -> Self {
+        Self {
+            hash: PerceptualHash::default(),
+            max_distance: 4,
+            max_skips: 32,
+            path: None,
+        }
+    }
+}
+
+/// Sources and perceptual hashes of the backgrounds used so far.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct SeenSet {
+    sources: HashSet<String>,
+    hashes: Vec<u64>,
+}
+
+impl SeenSet {
+    fn is_similar(&self, hash: u64, max_distance: u32) -> bool {
+        is_similar(&self.hashes, hash, max_distance)
+    }
+}
+
+fn is_similar<'a>(hashes: impl IntoIterator<Item = &'a u64>, hash: u64, max_distance: u32) -> bool {
+    hashes
+        .into_iter()
+        .any(|seen| (seen ^ hash).count_ones() <= max_distance)
+}
+
+#[derive(Debug, Default)]
+struct State {
+    /// Backgrounds used, by this run or earlier ones.
+    seen: SeenSet,
+    /// Sources served or skipped by this run.
+    known: HashSet<String>,
+    /// Hashes of the backgrounds served but not used yet, by their source.
+    served: HashMap<String, u64>,
+    /// First source skipped since the last background served. A wrapped provider that
+    /// [cycles](BackgroundProvider::cycles) went through all of its backgrounds once it comes
+    /// up again.
+    first_skipped: Option<String>,
+    /// When the oldest change of the seen set that wasn't saved yet was made.
+    changed: Option<Instant>,
+}
+
+/// Skips the backgrounds of the wrapped provider that were used before, either with the same
+/// source or a similar [`PerceptualHash`], as well as the ones already served by this run.
+///
+/// Backgrounds only count as used once [`BackgroundProvider::mark_used`] is called for them, so
+/// fetched backgrounds that end up unused, e.g. prefetched ones, are served again by later runs.
+/// The seen set is saved at most every [`SAVE_INTERVAL`] and when the provider is dropped.
+#[derive(Debug)]
+pub struct DedupProvider<P> {
+    provider: P,
+    options: DedupOptions,
+    state: Mutex<State>,
+    file: Option<AtomicFile>,
+}
+
+impl<P> DedupProvider<P> {
+    /// Loads the seen set from [`DedupOptions::path`]. A file that can't be read is ignored,
+    /// starting with nothing seen.
+    pub fn new(provider: P, options: DedupOptions) -> Self {
+        let file = options.path.clone().map(AtomicFile::new);
+
+        let seen = match &file {
+            Some(file) => {
+                let seen = file
+                    .read()
+                    .and_then(|bytes| match bytes {
+                        Some(bytes) => Ok(serde_json::from_slice(&bytes)?),
+                        None => Ok(SeenSet::default()),
+                    })
+                    .unwrap_or_else(|err| {
+                        warn!(
+                            "Ignoring the unreadable seen backgrounds in {}: {err}",
+                            file.path().display()
+                        );
+                        SeenSet::default()
+                    });
+                info!("Loaded {} seen backgrounds", seen.sources.len());
+                seen
+            }
+            None => SeenSet::default(),
+        };
+
+        Self {
+            provider,
+            options,
+            state: Mutex::new(State {
+                seen,
+                ..Default::default()
+            }),
+            file,
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Saves the seen set once its oldest unsaved change is [`SAVE_INTERVAL`] old.
+    async fn save_if_due(&self) {
+        self.save_older_than(SAVE_INTERVAL).await;
+    }
+
+    async fn save_older_than(&self, age: Duration) {
+        let Some(file) = &self.file else {
+            return;
+        };
+
+        let snapshot = {
+            let mut state = self.lock();
+            match state.changed {
+                Some(changed) if changed.elapsed() >= age => {}
+                _ => return,
+            }
+            state.changed = None;
+            file.snapshot(&state.seen)
+        };
+
+        if let Err(err) = file.write(snapshot).await {
+            warn!("Failed to save the seen backgrounds: {err}");
+        }
+    }
+}
+
+impl<P: BackgroundProvider> DedupProvider<P> {
+    /// Notes that `source` was skipped. If the wrapped provider cycles, fails once it came
+    /// around to the first source it skipped again, without a new background in between.
+    fn skip(&self, source: &str, reason: &str) -> Result<(), FetchBackgroundError> {
+        let mut state = self.lock();
+        state.known.insert(source.to_string());
+
+        if state.first_skipped.as_deref() == Some(source) && self.provider.cycles() {
+            error!("Every background of the provider was served before, stopping");
+            return Err(FetchBackgroundError::NoImages);
+        }
+        state
+            .first_skipped
+            .get_or_insert_with(|| source.to_string());
+
+        info!("Skipping {source}, {reason}");
+        Ok(())
+    }
+}
+
+impl<P: BackgroundProvider + Sync> BackgroundProvider for DedupProvider<P> {
+    async fn fetch_background(&self) -> Result<Background, FetchBackgroundError> {
+        for _ in 0..=self.options.max_skips {
+            let background = self.provider.fetch_background().await?;
+
+            let known = {
+                let state = self.lock();
+                state.seen.sources.contains(&background.source)
+                    || state.known.contains(&background.source)
+            };
+            if known {
+                self.skip(&background.source, "it was served before")?;
+                continue;
+            }
+
+            let kind = self.options.hash;
+            let (background, hash) = spawn_blocking(move || {
+                let hash = kind.hash(&background.image);
+                (background, hash)
+            })
+            .await
+            .map_err(std::io::Error::other)?;
+
+            let duplicate = {
+                let mut state = self.lock();
+                let max_distance = self.options.max_distance;
+                let duplicate = state.seen.is_similar(hash, max_distance)
+                    || is_similar(state.served.values(), hash, max_distance);
+
+                if !duplicate {
+                    // Known right away, so the source isn't hashed again
+                    state.known.insert(background.source.clone());
+                    state.served.insert(background.source.clone(), hash);
+                    state.first_skipped = None;
+                }
+                duplicate
+            };
+
+            if duplicate {
+                self.skip(&background.source, "it looks like one served before")?;
+                continue;
+            }
+
+            return Ok(background);
+        }
+
+        warn!(
+            "Got {} duplicate backgrounds in a row, giving up",
+            self.options.max_skips + 1
+        );
+
+        Err(FetchBackgroundError::NoImages)
+    }
+
+    /// Adds the background to the seen set, saving it once it's due.
+    async fn mark_used(&self, source: &str) {
+        {
+            let mut state = self.lock();
+            let hash = state.served.remove(source);
+
+            state.seen.sources.insert(source.to_string());
+            if let Some(hash) = hash
+                && !state.seen.is_similar(hash, self.options.max_distance)
+            {
+                state.seen.hashes.push(hash);
+            }
+            state.changed.get_or_insert_with(Instant::now);
+        }
+        self.save_if_due().await;
+
+        self.provider.mark_used(source).await;
+    }
+
+    async fn save_state(&self) {
+        self.save_older_than(Duration::ZERO).await;
+        self.provider.save_state().await;
+    }
+
+    fn cycles(&self) -> bool {
+        self.provider.cycles()
+    }
+}
+
+impl<P> Drop for DedupProvider<P> {
+    fn drop(&mut self) {
+        let state = self
+            .state
+            .get_mut()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let Some(file) = &self.file else {
+            return;
+        };
+        if state.changed.is_none() {
+            return;
+        }
+
+        if let Err(err) = file.write_blocking(&state.seen) {
+            warn!("Failed to save the seen backgrounds: {err}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::RgbImage;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// `a` is white on the left, `b` white on top.
+    fn background(source: &str) -> Background {
+        let left = source == "a";
+        let image = RgbImage::from_fn(16, 16, |x, y| {
+            let white = if left { x < 8 } else { y < 8 };
+            [if white { 255 } else { 0 }; 3].into()
+        });
+
+        Background::new(DynamicImage::ImageRgb8(image), source)
+    }
+
+    /// Serves `a` and `b` over and over.
+    #[derive(Default)]
+    struct Cycle {
+        fetches: AtomicUsize,
+    }
+
+    impl BackgroundProvider for Cycle {
+        async fn fetch_background(&self) -> Result<Background, FetchBackgroundError> {
+            let first = self
+                .fetches
+                .fetch_add(1, Ordering::Relaxed)
+                .is_multiple_of(2);
+            Ok(background(if first { "a" } else { "b" }))
+        }
+
+        fn cycles(&self) -> bool {
+            true
+        }
+    }
+
+    /// Serves `a` three times before `b`, like search results coming up again.
+    #[derive(Default)]
+    struct Repeat {
+        fetches: AtomicUsize,
+    }
+
+    impl BackgroundProvider for Repeat {
+        async fn fetch_background(&self) -> Result<Background, FetchBackgroundError> {
+            let fetches = self.fetches.fetch_add(1, Ordering::Relaxed);
+            Ok(background(if fetches < 3 { "a" } else { "b" }))
+        }
+    }
+
+    #[tokio::test]
+    async fn only_used_backgrounds_are_seen_and_cycles_stop() {
+        let path = std::env::temp_dir().join(format!("seen-{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let options = DedupOptions {
+            path: Some(path.clone()),
+            ..Default::default()
+        };
+
+        let first_run = DedupProvider::new(Cycle::default(), options.clone());
+        assert_eq!(first_run.fetch_background().await.unwrap().source, "a");
+        assert_eq!(first_run.fetch_background().await.unwrap().source, "b");
+        first_run.mark_used("b").await;
+        // Saved together with later changes, or when dropped
+        assert!(!path.exists());
+        drop(first_run);
+
+        // `a` was served but never used
+        let second_run = DedupProvider::new(Cycle::default(), options);
+        assert_eq!(second_run.fetch_background().await.unwrap().source, "a");
+        assert!(matches!(
+            second_run.fetch_background().await,
+            Err(FetchBackgroundError::NoImages)
+        ));
+        // Stopped after coming across `b` twice, instead of after `max_skips` duplicates
+        assert_eq!(second_run.provider.fetches.load(Ordering::Relaxed), 4);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn repeats_are_skipped_when_the_provider_does_not_cycle() {
+        let provider = DedupProvider::new(Repeat::default(), DedupOptions::default());
+
+        assert_eq!(provider.fetch_background().await.unwrap().source, "a");
+        assert_eq!(provider.fetch_background().await.unwrap().source, "b");
+        assert_eq!(provider.provider.fetches.load(Ordering::Relaxed), 4);
+    }
+}
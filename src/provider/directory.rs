@@ -0,0 +1,275 @@
+use crate::provider::{Background, BackgroundProvider, FetchBackgroundError};
+use log::info;
+use rand::distr::Distribution;
+use rand::distr::weighted::WeightedIndex;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use serde::{Deserialize, Deserializer};
+use std::collections::{HashMap, VecDeque};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use tokio::sync::Mutex;
+use tokio::task::spawn_blocking;
+
+/// In which order the images of a directory are served.
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Order {
+    /// Every image once per pass, in random order.
+    #[default]
+    Shuffled,
+    /// Every image once per pass, sorted by path.
+    Sequential,
+    /// Images drawn with replacement, heavier ones more often, see
+    /// [`DirectoryOptions::weights`]. A pass is as many draws as there are images.
+    Weighted,
+}
+
+/// What happens once every image of a pass has been served.
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Exhaustion {
+    /// Start a new pass.
+    #[default]
+    Cycle,
+    /// Fail with [`FetchBackgroundError::NoImages`].
+    Stop,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct DirectoryOptions {
+    pub recursive: bool,
+    /// Extensions of the files to pick up, compared case-insensitively.
+    pub extensions: Vec<String>,
+    pub order: Order,
+    /// Weights of files and subdirectories relative to the root (`""` is the root itself)
+    /// for [`Order::Weighted`]. A file weighs as much as its closest weighted ancestor, or `1.0`
+    /// if there is none.
+    pub weights: HashMap<PathBuf, f32>,
+    pub exhaustion: Exhaustion,
+    pub seed: Option<u64>,
+}
+
+impl Default for DirectoryOptions {
+    fn default() -> Self {
+        Self {
+            recursive: false,
+            extensions: ["png", "jpg", "jpeg", "webp", "gif", "bmp"]
+                .map(String::from)
+                .to_vec(),
+            order: Order::default(),
+            weights: HashMap::new(),
+            exhaustion: Exhaustion::default(),
+            seed: None,
+        }
+    }
+}
+
+/// Serves backgrounds from a local folder, so the pipeline can run offline.
+#[derive(Debug)]
+pub struct DirectoryProvider {
+    files: Vec<PathBuf>,
+    options: DirectoryOptions,
+    /// Distribution of the files for [`Order::Weighted`].
+    weighted: Option<WeightedIndex<f32>>,
+    state: Mutex<State>,
+}
+
+#[derive(Debug)]
+struct State {
+    queue: VecDeque<usize>,
+    passes: usize,
+    rng: StdRng,
+}
+
+impl DirectoryProvider {
+    pub fn new(root: impl Into<PathBuf>, options: DirectoryOptions) -> std::io::Result<Self> {
+        let root = root.into();
+        let mut files = Vec::new();
+        collect_files(&root, &options, &mut files)?;
+        files.sort();
+
+        info!("Found {} images in {}", files.len(), root.display());
+
+        let rng = match options.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_os_rng(),
+        };
+
+        let weighted = match options.order {
+            Order::Weighted if !files.is_empty() => {
+                let weights = files.iter().map(|file| weight_of(&root, &options, file));
+                Some(WeightedIndex::new(weights).map_err(|err| {
+                    std::io::Error::new(ErrorKind::InvalidInput, format!("Invalid weights: {err}"))
+                })?)
+            }
+            _ => None,
+        };
+
+        Ok(Self {
+            files,
+            options,
+            weighted,
+            state: Mutex::new(State {
+                queue: VecDeque::new(),
+                passes: 0,
+                rng,
+            }),
+        })
+    }
+
+    fn next_pass(&self, rng: &mut StdRng) -> VecDeque<usize> {
+        if let Some(weighted) = &self.weighted {
+            return weighted.sample_iter(rng).take(self.files.len()).collect();
+        }
+
+        let mut indices = (0..self.files.len()).collect::<Vec<_>>();
+        if let Order::Shuffled = self.options.order {
+            indices.shuffle(rng);
+        }
+
+        indices.into()
+    }
+}
+
+/// Weight of the closest ancestor of `file`, including the file itself, that has one.
+fn weight_of(root: &Path, options: &DirectoryOptions, file: &Path) -> f32 {
+    let Ok(relative) = file.strip_prefix(root) else {
+        return 1.0;
+    };
+
+    relative
+        .ancestors()
+        .find_map(|path| options.weights.get(path))
+        .copied()
+        .unwrap_or(1.0)
+}
+
+fn collect_files(
+    dir: &Path,
+    options: &DirectoryOptions,
+    out: &mut Vec<PathBuf>,
+) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+
+        if path.is_dir() {
+            if options.recursive {
+                collect_files(&path, options, out)?;
+            }
+            continue;
+        }
+
+        let matches = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .is_some_and(|e| options.extensions.iter().any(|x| x.eq_ignore_ascii_case(e)));
+
+        if matches {
+            out.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+impl<'de> Deserialize<'de> for DirectoryProvider {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        struct Helper {
+            path: PathBuf,
+            #[serde(flatten)]
+            options: DirectoryOptions,
+        }
+
+        let raw = Helper::deserialize(deserializer)?;
+
+        DirectoryProvider::new(raw.path, raw.options).map_err(serde::de::Error::custom)
+    }
+}
+
+impl BackgroundProvider for DirectoryProvider {
+    async fn fetch_background(&self) -> Result<Background, FetchBackgroundError> {
+        if self.files.is_empty() {
+            return Err(FetchBackgroundError::NoImages);
+        }
+
+        let mut state = self.state.lock().await;
+
+        if state.queue.is_empty() {
+            if state.passes > 0 && matches!(self.options.exhaustion, Exhaustion::Stop) {
+                return Err(FetchBackgroundError::NoImages);
+            }
+
+            let State { queue, passes, rng } = &mut *state;
+            *queue = self.next_pass(rng);
+            *passes += 1;
+        }
+
+        let path = self.files[state.queue.pop_front().unwrap()].clone();
+        drop(state);
+
+        info!("Loading image {}", path.display());
+        let source = path.display().to_string();
+        let image = spawn_blocking(move || image::open(path))
+            .await
+            .map_err(std::io::Error::other)??;
+
+        Ok(Background::new(image, source))
+    }
+
+    /// Passes in shuffled or sequential order have every image once, weighted draws don't.
+    fn cycles(&self) -> bool {
+        !matches!(self.options.order, Order::Weighted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn weighted_order_draws_by_weight() {
+        let root = std::env::temp_dir().join(format!("weighted-order-{}", std::process::id()));
+        for dir in ["heavy", "light"] {
+            std::fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        for file in ["heavy/a.png", "heavy/b.png", "light/c.png", "light/d.png"] {
+            std::fs::write(root.join(file), []).unwrap();
+        }
+
+        let options = DirectoryOptions {
+            recursive: true,
+            order: Order::Weighted,
+            weights: HashMap::from([
+                (PathBuf::from("heavy"), 3.0),
+                (PathBuf::from("light/d.png"), 0.0),
+            ]),
+            seed: Some(7),
+            ..Default::default()
+        };
+        let provider = DirectoryProvider::new(&root, options).unwrap();
+        std::fs::remove_dir_all(&root).unwrap();
+
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut counts = HashMap::<&Path, usize>::new();
+        for _ in 0..5000 {
+            for i in provider.next_pass(&mut rng) {
+                let file = provider.files[i].strip_prefix(&root).unwrap();
+                *counts.entry(file).or_default() += 1;
+            }
+        }
+
+        // 3 : 3 : 1 : 0 out of 20000 draws
+        let share = |file: &str| counts.get(Path::new(file)).copied().unwrap_or(0) as f32 / 20000.0;
+        assert!((share("heavy/a.png") - 3.0 / 7.0).abs() < 0.02);
+        assert!((share("heavy/b.png") - 3.0 / 7.0).abs() < 0.02);
+        assert!((share("light/c.png") - 1.0 / 7.0).abs() < 0.02);
+        assert_eq!(share("light/d.png"), 0.0);
+    }
+}
@@ -0,0 +1,100 @@
+use serde::Serialize;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::sync::Mutex;
+use tokio::task::spawn_blocking;
+
+/// A file that is only ever replaced as a whole, so readers never see a half-written one.
+///
+/// [`Snapshot`]s are numbered when they are taken, a snapshot is skipped if a newer one was
+/// written already, so concurrent saves can't replace the file with older contents.
+#[derive(Debug)]
+pub(crate) struct AtomicFile {
+    path: PathBuf,
+    /// Number of the last snapshot taken.
+    taken: AtomicU64,
+    /// Number of the snapshot on disk, held while writing.
+    written: Mutex<u64>,
+}
+
+impl AtomicFile {
+    pub(crate) fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            taken: AtomicU64::new(0),
+            written: Mutex::new(0),
+        }
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Contents of the file, `None` if there is none.
+    pub(crate) fn read(&self) -> std::io::Result<Option<Vec<u8>>> {
+        match std::fs::read(&self.path) {
+            Ok(bytes) => Ok(Some(bytes)),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Serializes `value` to be written, to be called while `value` is locked so snapshots
+    /// are numbered in the order of the changes.
+    pub(crate) fn snapshot(&self, value: &impl Serialize) -> Snapshot {
+        Snapshot {
+            number: self.taken.fetch_add(1, Ordering::Relaxed) + 1,
+            contents: serde_json::to_vec(value),
+        }
+    }
+
+    /// Writes the snapshot on the blocking pool, unless a newer one was written already.
+    pub(crate) async fn write(&self, snapshot: Snapshot) -> std::io::Result<()> {
+        let mut written = self.written.lock().await;
+        if *written >= snapshot.number {
+            return Ok(());
+        }
+
+        let path = self.path.clone();
+        let contents = snapshot.contents?;
+        spawn_blocking(move || write_atomic(&path, &contents))
+            .await
+            .map_err(std::io::Error::other)??;
+        *written = snapshot.number;
+
+        Ok(())
+    }
+
+    /// Writes `value` right away, for when there is no runtime to await the write on, e.g.
+    /// while dropping.
+    pub(crate) fn write_blocking(&self, value: &impl Serialize) -> std::io::Result<()> {
+        write_atomic(&self.path, &serde_json::to_vec(value)?)
+    }
+}
+
+/// Contents of an [`AtomicFile`] taken by [`AtomicFile::snapshot`].
+#[derive(Debug)]
+pub(crate) struct Snapshot {
+    number: u64,
+    contents: serde_json::Result<Vec<u8>>,
+}
+
+/// Writes to a temporary file next to `path` and renames it over `path`. The temporary file
+/// is unique to the process and the write, so concurrent writes don't clobber each other.
+pub(crate) fn write_atomic(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    static WRITES: AtomicU64 = AtomicU64::new(0);
+
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(format!(
+        ".{}.{}.tmp",
+        std::process::id(),
+        WRITES.fetch_add(1, Ordering::Relaxed)
+    ));
+    let tmp = PathBuf::from(tmp);
+
+    std::fs::write(&tmp, contents)?;
+    std::fs::rename(&tmp, path).inspect_err(|_| {
+        let _ = std::fs::remove_file(&tmp);
+    })
+}
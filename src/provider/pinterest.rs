@@ -1,116 +1,326 @@
-use crate::provider::{BackgroundProvider, FetchBackgroundError};
+pub mod filter;
+pub mod session;
+pub mod state;
+
+use crate::provider::cache::BackgroundCache;
+use crate::provider::persist::AtomicFile;
+use crate::provider::pinterest::filter::PinterestFilter;
+use crate::provider::pinterest::session::PinterestSession;
+use crate::provider::pinterest::state::{PinterestState, QueryState};
+use crate::provider::{Background, BackgroundProvider, FetchBackgroundError};
+use crate::weight::{default_weight, valid_weights};
 use derive_new::new;
-use image::DynamicImage;
-use lazy_static::lazy_static;
-use log::info;
-use reqwest::header::HeaderMap;
-use reqwest::{header, Client};
+use log::{error, info, warn};
+use rand::seq::IndexedRandom;
+use reqwest::{Client, StatusCode};
 use serde_json::{json, Value};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use serde::de::Error as _;
 use serde::{Deserialize, Deserializer};
+use thiserror::Error;
 use tokio::sync::Mutex;
 
-lazy_static! {
-    static ref HEADERS: HeaderMap = {
-        let mut headers = HeaderMap::new();
-        headers.insert(
-            "User-Agent",
-            "Mozilla/5.0 (X11; Linux x86_64; rv:143.0) Gecko/20100101 Firefox/143.0"
-                .parse()
-                .unwrap(),
-        );
-        headers.insert(
-            "Accept",
-            "application/json, text/javascript, */*, q=0.01"
-                .parse()
-                .unwrap(),
-        );
-        headers.insert("Referer", "https://www.pinterest.com/".parse().unwrap());
-        headers.insert("X-Requested-With", "XMLHttpRequest".parse().unwrap());
-        headers.insert("X-APP-VERSION", "0dd1dca".parse().unwrap());
-        headers.insert(
-            "Content-Type",
-            "application/x-www-form-urlencoded".parse().unwrap(),
-        );
-        headers.insert(
-            "X-CSRFToken",
-            "720e1cf655eb75932e9b71ab79ec1d21".parse().unwrap(),
-        );
-        headers.insert("X-Pinterest-AppState", "background".parse().unwrap());
-        headers.insert(
-            "X-Pinterest-Source-Url",
-            "/search/pins/?q=dreamcore%20background".parse().unwrap(),
-        );
-        headers.insert(
-            "X-Pinterest-PWS-Handler",
-            "www/search/[scope].js".parse().unwrap(),
-        );
-        headers.insert("screen-dpr", "1".parse().unwrap());
-        headers.insert("X-B3-TraceId", "7425cd664415310a".parse().unwrap());
-        headers.insert("X-B3-SpanId", "0ec54aeb368ea87b".parse().unwrap());
-        headers.insert("X-B3-ParentSpanId", "7425cd664415310a".parse().unwrap());
-        headers.insert("X-B3-Flags", "0".parse().unwrap());
-        headers.insert("Origin", "https://www.pinterest.com".parse().unwrap());
-        headers.insert("Sec-GPC", "1".parse().unwrap());
-        headers.insert("Connection", "keep-alive".parse().unwrap());
-        headers.insert(header::COOKIE, "csrftoken=720e1cf655eb75932e9b71ab79ec1d21; _pinterest_sess=TWc9PSZmK293QnQxeDE0ckZyU01ha1R4SndkZGtJV01FSUhvVTdGeXFqZTQxNHNuSjY2RnFxV1VpaXQrMTBxQjBJcWlDQ2N1Zks4ZStTcXIvSnMvMXkwb04ySUI4WmlaMkV2OU1JT1h0L2FBOTdxcz0mQjZDVFc0aEF5Q1hHNGFsWllLWS9JWDBETWNZPQ==; _auth=0; _routing_id=\"f2d1f224-013f-4541-ad36-a201ac4239be\"; g_state={\"i_l\":1,\"i_ll\":1760585975395,\"i_b\":\"zQ3ojrIu/3MF354oYANCjS0QXf7iq9Q886RCjgtXGdk\",\"i_p\":1760593179991}".parse().unwrap());
-        headers.insert("Sec-Fetch-Dest", "empty".parse().unwrap());
-        headers.insert("Sec-Fetch-Mode", "cors".parse().unwrap());
-        headers.insert("Sec-Fetch-Site", "same-origin".parse().unwrap());
-        headers.insert("Pragma", "no-cache".parse().unwrap());
-        headers.insert("Cache-Control", "no-cache".parse().unwrap());
-        headers.insert("TE", "trailers".parse().unwrap());
-
-        headers
-    };
+/// The pool is refilled in the background of a download once it holds fewer URLs than this.
+const REFILL_THRESHOLD: usize = 8;
+/// How many search pages in a row may bring no new URLs before giving up.
+const MAX_STALE_PAGES: usize = 3;
+/// The state is checkpointed after this many fetches, besides after every search.
+const CHECKPOINT_INTERVAL: usize = 16;
+
+#[derive(Debug, Error)]
+pub enum QueryError {
+    #[error("No search queries given")]
+    Empty,
+
+    #[error("Query weights must be non-negative and not all zero")]
+    InvalidWeights,
 }
 
-#[derive(Debug, new)]
-pub struct PinterestProvider {
+/// A search query and how often it's picked relative to the other queries.
+#[derive(Debug, Clone, new)]
+pub struct PinterestQuery {
     #[new(into)]
-    query: String,
-    #[new(default)]
-    image_pool: Mutex<ImagePool>,
-    #[new(default)]
+    pub query: String,
+    pub weight: f32,
+}
+
+/// A query as written in a config, either just the text or with a weight.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum QueryEntry {
+    Text(String),
+    Weighted {
+        query: String,
+        #[serde(default = "default_weight")]
+        weight: f32,
+    },
+}
+
+/// Search results of one query, paged through independently of the other queries.
+#[derive(Debug)]
+struct QueryPool {
+    query: PinterestQuery,
+    images: Mutex<ImagePool>,
+    /// Held while searching, so concurrent fetches don't request the same page twice.
+    refill: Mutex<()>,
+    /// Set once the searches stop bringing new URLs, the query isn't picked anymore.
+    exhausted: AtomicBool,
+}
+
+impl QueryPool {
+    fn new(query: PinterestQuery, bookmark: Option<String>) -> Self {
+        Self {
+            query,
+            images: Mutex::new(ImagePool::new(bookmark)),
+            refill: Mutex::new(()),
+            exhausted: AtomicBool::new(false),
+        }
+    }
+
+    fn state(&self, pool: &ImagePool) -> QueryState {
+        // An exhausted query starts over in the next run
+        let exhausted = self.exhausted.load(Ordering::Relaxed);
+
+        // URLs being fetched or not used yet are the first to fetch in the next run
+        let images = pool.images.iter().chain(&pool.fetched).cloned().collect();
+
+        QueryState {
+            query: self.query.query.clone(),
+            bookmark: pool.bookmark.clone().filter(|_| !exhausted),
+            images,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct PinterestProvider {
+    queries: Vec<QueryPool>,
     client: Client,
+    /// Checked before downloading, so URLs seen in earlier runs aren't downloaded again.
+    cache: Option<Arc<BackgroundCache>>,
+    session: PinterestSession,
+    /// CSRF token of the session, bootstrapped on the first search unless configured.
+    csrf_token: Mutex<Option<String>>,
+    filter: PinterestFilter,
+    /// File the state is checkpointed to.
+    checkpoint: Option<AtomicFile>,
+    fetches: AtomicUsize,
 }
 
 impl PinterestProvider {
+    pub fn new(query: impl Into<String>) -> Self {
+        Self::with_bookmark(query, None)
+    }
+
     pub fn with_bookmark(query: impl Into<String>, bookmark: Option<String>) -> Self {
+        Self::from_pools(vec![QueryPool::new(
+            PinterestQuery::new(query, default_weight()),
+            bookmark,
+        )])
+    }
+
+    /// Searches several queries, each fetch picks one of them by weight, so a batch mixes
+    /// their results. Every query keeps its own bookmark and pool of URLs.
+    pub fn with_queries(
+        queries: impl IntoIterator<Item = PinterestQuery>,
+    ) -> Result<Self, QueryError> {
+        Self::with_bookmarks(queries.into_iter().map(|query| (query, None)))
+    }
+
+    fn with_bookmarks(
+        queries: impl IntoIterator<Item = (PinterestQuery, Option<String>)>,
+    ) -> Result<Self, QueryError> {
+        let pools = queries
+            .into_iter()
+            .map(|(query, bookmark)| QueryPool::new(query, bookmark))
+            .collect::<Vec<_>>();
+
+        if pools.is_empty() {
+            return Err(QueryError::Empty);
+        }
+
+        if !valid_weights(pools.iter().map(|p| p.query.weight)) {
+            return Err(QueryError::InvalidWeights);
+        }
+
+        Ok(Self::from_pools(pools))
+    }
+
+    fn from_pools(queries: Vec<QueryPool>) -> Self {
         Self {
-            query: query.into(),
-            image_pool: Mutex::new(ImagePool::new(bookmark)),
-            client: Client::new()
+            queries,
+            client: Client::new(),
+            cache: None,
+            session: PinterestSession::default().with_env(),
+            csrf_token: Mutex::new(None),
+            filter: PinterestFilter::default(),
+            checkpoint: None,
+            fetches: AtomicUsize::new(0),
         }
     }
-}
 
-#[derive(Debug, Default, new)]
-struct ImagePool {
-    #[new(default)]
-    images: Vec<String>,
-    bookmark: Option<String>,
-}
+    pub fn with_session(mut self, session: PinterestSession) -> Self {
+        self.session = session;
+        self
+    }
 
-impl<'de> Deserialize<'de> for PinterestProvider {
-    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
-    where
-        D: Deserializer<'de>,
-    {
-        #[derive(Deserialize)]
-        struct Helper {
-            query: String,
-            bookmark: Option<String>,
+    pub fn with_filter(mut self, filter: PinterestFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// Takes over the bookmarks and URLs of the queries the state has in common with this
+    /// provider.
+    pub fn with_state(mut self, state: PinterestState) -> Self {
+        for saved in state.queries {
+            let Some(query) = self
+                .queries
+                .iter_mut()
+                .find(|query| query.query.query == saved.query)
+            else {
+                info!(
+                    "Ignoring the saved state of {:?}, it isn't searched anymore",
+                    saved.query
+                );
+                continue;
+            };
+
+            info!(
+                "Resuming {:?} with {} images left",
+                saved.query,
+                saved.images.len()
+            );
+            let pool = query.images.get_mut();
+            pool.queued.extend(saved.images.iter().cloned());
+            pool.images = saved.images;
+            pool.bookmark = saved.bookmark;
         }
 
-        let raw = Helper::deserialize(deserializer)?;
+        self
+    }
 
-        Ok(PinterestProvider::with_bookmark(raw.query, raw.bookmark))
+    /// Resumes from the state saved in `path` by an earlier run, if there is one, and saves the
+    /// state there after every search, every few fetches and when the provider is dropped.
+    ///
+    /// URLs of backgrounds fetched but not marked as used, e.g. ones prefetched right before
+    /// exiting, stay part of the state until [`BackgroundProvider::mark_used`] is called for
+    /// them. A state that can't be read is ignored, starting over at the first page.
+    pub fn with_checkpoint(self, path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+
+        let mut provider = match PinterestState::load(&path) {
+            Ok(Some(state)) => self.with_state(state),
+            Ok(None) => self,
+            Err(err) => {
+                warn!(
+                    "Ignoring the unreadable Pinterest state in {}: {err}",
+                    path.display()
+                );
+                self
+            }
+        };
+        provider.checkpoint = Some(AtomicFile::new(path));
+
+        provider
     }
-}
 
-impl ImagePool {
-    async fn populate(&mut self, query: &str, client: Client) -> Result<(), reqwest::Error> {
+    /// Bookmarks and unconsumed URLs of every query.
+    pub async fn state(&self) -> PinterestState {
+        let mut queries = Vec::with_capacity(self.queries.len());
+        for query in &self.queries {
+            let pool = query.images.lock().await;
+            queries.push(query.state(&pool));
+        }
+
+        PinterestState { queries }
+    }
+
+    /// Saves the state to the checkpoint file, if there is one.
+    pub async fn checkpoint(&self) -> std::io::Result<()> {
+        let Some(file) = &self.checkpoint else {
+            return Ok(());
+        };
+
+        let state = self.state().await;
+        file.write(file.snapshot(&state)).await
+    }
+
+    async fn try_checkpoint(&self) {
+        if let Err(err) = self.checkpoint().await {
+            warn!("Failed to save the Pinterest state: {err}");
+        }
+    }
+
+    pub fn with_cache(mut self, cache: Arc<BackgroundCache>) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// Searches for the next page of URLs unless the pool holds enough of them by the time
+    /// the search lock is taken. The pool itself stays unlocked during the search.
+    async fn refill(&self, query: &QueryPool) -> Result<(), FetchBackgroundError> {
+        let _searching = query.refill.lock().await;
+
+        let bookmark = {
+            let pool = query.images.lock().await;
+            if pool.images.len() >= REFILL_THRESHOLD {
+                return Ok(());
+            }
+            pool.bookmark.clone()
+        };
+
+        info!(
+            "Image pool of {:?} is running low, fetching new batch...",
+            query.query.query
+        );
+        let (images, bookmark) = self.search(&query.query.query, bookmark).await?;
+
+        let mut pool = query.images.lock().await;
+        let ImagePool { queued, .. } = &mut *pool;
+        let images = images
+            .into_iter()
+            .filter(|url| queued.insert(url.clone()))
+            .collect::<Vec<_>>();
+        // Older URLs are popped first
+        pool.images.splice(0..0, images);
+
+        if let Some(bm) = bookmark {
+            info!("Resource bookmark: {bm}");
+            pool.bookmark.replace(bm);
+        }
+
+        drop(pool);
+        self.try_checkpoint().await;
+
+        Ok(())
+    }
+
+    /// The CSRF token from the session, or the one bootstrapped from the landing page.
+    async fn csrf_token(&self) -> Result<String, FetchBackgroundError> {
+        if let Some(token) = &self.session.csrf_token {
+            return Ok(token.clone());
+        }
+
+        let mut cached = self.csrf_token.lock().await;
+        if let Some(token) = &*cached {
+            return Ok(token.clone());
+        }
+
+        let token = self.session.bootstrap_csrf(&self.client).await?;
+        cached.replace(token.clone());
+
+        Ok(token)
+    }
+
+    /// One page of search results, the image URLs and the bookmark of the next page.
+    async fn search(
+        &self,
+        query: &str,
+        bookmark: Option<String>,
+    ) -> Result<(Vec<String>, Option<String>), FetchBackgroundError> {
         let source_url = format!("/search/pins/?q={query}");
         let source_url = urlencoding::encode(&source_url);
 
@@ -141,7 +351,7 @@ impl ImagePool {
                     "filters": null,
                     "rs": "direct_navigation",
                     "redux_normalize_feed": true,
-                    "bookmarks": self.bookmark.iter().cloned().collect::<Vec<_>>()
+                    "bookmarks": bookmark.into_iter().collect::<Vec<_>>()
                 },
             "context": {}
         });
@@ -150,39 +360,188 @@ impl ImagePool {
         form.insert("source_url", source_url);
         form.insert("data", data.to_string().into());
 
-        let res = client
-            .post("https://www.pinterest.com/resource/BaseSearchResource/get/")
-            .headers(HEADERS.clone())
+        let csrf_token = self.csrf_token().await?;
+        let page_url = format!("/search/pins/?q={}", urlencoding::encode(query));
+        let headers = self.session.search_headers(&csrf_token, &page_url)?;
+
+        let res = self
+            .client
+            .post(self.session.url("/resource/BaseSearchResource/get/"))
+            .headers(headers)
             .form(&form)
             .send()
-            .await?
-            .error_for_status()?
+            .await?;
+
+        if matches!(
+            res.status(),
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN
+        ) {
+            error!(
+                "Pinterest rejected the session with {}, the CSRF token or session cookie \
+                 probably expired",
+                res.status()
+            );
+            // A bootstrapped token is fetched again by the next search
+            self.csrf_token.lock().await.take();
+        }
+
+        let res = FetchBackgroundError::check_status(res)?
             .json::<Value>()
             .await?;
 
-        extract_image_urls(&res, &mut self.images);
+        let mut images = Vec::new();
+        let rejected = extract_image_urls(&res, &self.filter, &mut images);
+        if rejected > 0 {
+            info!("Filtered out {rejected} search results");
+        }
 
-        if let Some(bm) = extract_bookmark(&res) {
-            info!("Resource bookmark: {bm}");
-            self.bookmark.replace(bm);
+        Ok((images, extract_bookmark(&res)))
+    }
+
+    /// Pops a URL of a query picked by weight, along with how many URLs the query has left.
+    async fn next_url(&self) -> Result<(String, usize, &QueryPool), FetchBackgroundError> {
+        loop {
+            let available = self
+                .queries
+                .iter()
+                .filter(|query| !query.exhausted.load(Ordering::Relaxed))
+                .collect::<Vec<_>>();
+            let Ok(query) = available.choose_weighted(&mut rand::rng(), |q| q.query.weight) else {
+                return Err(FetchBackgroundError::NoImages);
+            };
+
+            match self.pop_url(query).await {
+                Err(FetchBackgroundError::NoImages) => {
+                    info!("No more images for {:?}", query.query.query);
+                    query.exhausted.store(true, Ordering::Relaxed);
+                }
+                result => return result.map(|(url, rest)| (url, rest, *query)),
+            }
         }
+    }
 
-        Ok(())
+    async fn pop_url(&self, query: &QueryPool) -> Result<(String, usize), FetchBackgroundError> {
+        for _ in 0..=MAX_STALE_PAGES {
+            let mut pool = query.images.lock().await;
+
+            if let Some(url) = pool.images.pop() {
+                pool.fetched.insert(url.clone());
+                return Ok((url, pool.images.len()));
+            }
+
+            drop(pool);
+            self.refill(query).await?;
+        }
+
+        Err(FetchBackgroundError::NoImages)
+    }
+}
+
+#[derive(Debug, Default, new)]
+struct ImagePool {
+    #[new(default)]
+    images: Vec<String>,
+    bookmark: Option<String>,
+    /// Every URL queued so far, search pages overlap.
+    #[new(default)]
+    queued: HashSet<String>,
+    /// URLs popped but not used yet.
+    #[new(default)]
+    fetched: HashSet<String>,
+}
+
+/// A last resort with blocking IO, interrupted runs save the state through
+/// [`BackgroundProvider::save_state`] before exiting.
+impl Drop for PinterestProvider {
+    fn drop(&mut self) {
+        let Some(file) = &self.checkpoint else {
+            return;
+        };
+
+        let queries = self
+            .queries
+            .iter_mut()
+            .map(|query| {
+                let pool = std::mem::take(query.images.get_mut());
+                query.state(&pool)
+            })
+            .collect();
+
+        match file.write_blocking(&PinterestState { queries }) {
+            Ok(()) => info!("Saved the Pinterest state to {}", file.path().display()),
+            Err(err) => warn!("Failed to save the Pinterest state: {err}"),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for PinterestProvider {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        struct Helper {
+            #[serde(default)]
+            query: Option<String>,
+            /// Searched besides `query`, which the bookmark belongs to.
+            #[serde(default)]
+            queries: Vec<QueryEntry>,
+            bookmark: Option<String>,
+            #[serde(default)]
+            session: PinterestSession,
+            #[serde(default)]
+            filter: PinterestFilter,
+            /// Checkpoint file, its state takes precedence over `bookmark`.
+            #[serde(default)]
+            state: Option<PathBuf>,
+        }
+
+        let raw = Helper::deserialize(deserializer)?;
+
+        let queries = raw
+            .query
+            .map(|query| (PinterestQuery::new(query, default_weight()), raw.bookmark))
+            .into_iter()
+            .chain(raw.queries.into_iter().map(|entry| {
+                let query = match entry {
+                    QueryEntry::Text(query) => PinterestQuery::new(query, default_weight()),
+                    QueryEntry::Weighted { query, weight } => PinterestQuery::new(query, weight),
+                };
+                (query, None)
+            }));
+
+        let provider = PinterestProvider::with_bookmarks(queries)
+            .map_err(D::Error::custom)?
+            .with_session(raw.session.with_env())
+            .with_filter(raw.filter);
+
+        match raw.state {
+            Some(path) => Ok(provider.with_checkpoint(path)),
+            None => Ok(provider),
+        }
     }
 }
 
+/// Pushes the URLs of the results passing the filter, returns how many results had an image
+/// but were rejected.
 #[inline(always)]
-fn extract_image_urls(json: &Value, out: &mut Vec<String>) {
+fn extract_image_urls(json: &Value, filter: &PinterestFilter, out: &mut Vec<String>) -> usize {
+    let mut rejected = 0;
+
     if let Some(arr) = json
         .pointer("/resource_response/data/results")
         .and_then(Value::as_array)
     {
         for item in arr {
-            if let Some(url) = item.pointer("/images/orig/url").and_then(Value::as_str) {
-                out.push(url.to_string());
+            match filter.accept(item) {
+                Some(url) => out.push(url.to_string()),
+                None if item.pointer("/images/orig/url").is_some() => rejected += 1,
+                None => {}
             }
         }
     }
+
+    rejected
 }
 
 #[inline(always)]
@@ -192,32 +551,63 @@ fn extract_bookmark(json: &Value) -> Option<String> {
         .map(str::to_owned)
 }
 
-async fn download_image(client: Client, url: String) -> Result<DynamicImage, FetchBackgroundError> {
-    let bytes = client.get(&url).send().await?.bytes().await?;
+async fn download_image(client: Client, url: String) -> Result<Background, FetchBackgroundError> {
+    let response = FetchBackgroundError::check_status(client.get(&url).send().await?)?;
+    let bytes = response.bytes().await?;
     let img = image::load_from_memory(&bytes)?;
-    Ok(img)
+
+    Ok(Background {
+        image: img,
+        source: url,
+        encoded: Some(Arc::from(&bytes[..])),
+    })
 }
 
 impl BackgroundProvider for PinterestProvider {
-    async fn fetch_background(&self) -> Result<DynamicImage, FetchBackgroundError> {
-        let mut pool = self.image_pool.lock().await;
+    async fn fetch_background(&self) -> Result<Background, FetchBackgroundError> {
+        let (image_link, rest, query) = self.next_url().await?;
 
-        if pool.images.is_empty() {
-            info!("Image pool is empty, fetching new batch...");
+        let fetches = self.fetches.fetch_add(1, Ordering::Relaxed) + 1;
+        if fetches.is_multiple_of(CHECKPOINT_INTERVAL) {
+            self.try_checkpoint().await;
+        }
 
-            pool.populate(&self.query, self.client.clone()).await?;
+        if let Some(cache) = &self.cache
+            && let Some(background) = cache.get(&image_link).await
+        {
+            info!("Using cached image {image_link}, {rest} images rest in pool");
+            return Ok(background);
+        }
 
-            if pool.images.is_empty() {
-                return Err(FetchBackgroundError::NoImages);
+        info!("Downloading image {image_link}, {rest} images rest in pool");
+        let download = download_image(self.client.clone(), image_link.clone());
+
+        let background = if rest < REFILL_THRESHOLD {
+            let (background, refilled) = tokio::join!(download, self.refill(query));
+            if let Err(err) = refilled {
+                warn!("Failed to refill the image pool: {err}");
             }
-        }
+            background
+        } else {
+            download.await
+        };
 
-        let image_link = pool.images.pop().unwrap();
-        let rest = pool.images.len();
+        if background.is_err() {
+            // A broken URL isn't worth another try in the next run
+            query.images.lock().await.fetched.remove(&image_link);
+        }
+        background
+    }
 
-        drop(pool);
+    async fn mark_used(&self, source: &str) {
+        for query in &self.queries {
+            if query.images.lock().await.fetched.remove(source) {
+                return;
+            }
+        }
+    }
 
-        info!("Downloading image {image_link}, {rest} images rest in pool");
-        download_image(self.client.clone(), image_link).await
+    async fn save_state(&self) {
+        self.try_checkpoint().await;
     }
 }
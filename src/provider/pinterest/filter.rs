@@ -0,0 +1,94 @@
+use serde::Deserialize;
+use serde_json::Value;
+use std::ops::RangeInclusive;
+
+/// Which search results [`PinterestProvider`](super::PinterestProvider) downloads, set with the
+/// `filter` table of its config. Judged by the metadata of the results, so rejected images
+/// are never downloaded.
+///
+/// ```toml
+/// [provider.filter]
+/// min_width = 1024
+/// min_height = 768
+/// aspect_ratio = { start = 0.5, end = 2.5 }
+/// extensions = ["jpg", "png", "webp"]
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct PinterestFilter {
+    /// Minimum width of the original image in pixels.
+    pub min_width: u32,
+    /// Minimum height of the original image in pixels.
+    pub min_height: u32,
+    /// Allowed range of width divided by height.
+    pub aspect_ratio: Option<RangeInclusive<f32>>,
+    /// Skip pins that are ads.
+    pub exclude_promoted: bool,
+    /// Skip video pins, whose images are only thumbnails.
+    pub exclude_videos: bool,
+    /// Allowed file extensions of the original image, case-insensitive. Empty allows all.
+    pub extensions: Vec<String>,
+}
+
+impl Default for PinterestFilter {
+    fn default() -> Self {
+        Self {
+            min_width: 0,
+            min_height: 0,
+            aspect_ratio: None,
+            exclude_promoted: true,
+            exclude_videos: true,
+            extensions: Vec::new(),
+        }
+    }
+}
+
+impl PinterestFilter {
+    /// The URL of the original image of a search result, if the result passes the filter.
+    pub(crate) fn accept<'a>(&self, pin: &'a Value) -> Option<&'a str> {
+        let original = pin.pointer("/images/orig")?;
+        let url = original.get("url")?.as_str()?;
+
+        if self.exclude_promoted && pin.get("is_promoted").and_then(Value::as_bool) == Some(true) {
+            return None;
+        }
+        if self.exclude_videos && pin.get("videos").is_some_and(|videos| !videos.is_null()) {
+            return None;
+        }
+
+        let dimension = |name| original.get(name).and_then(Value::as_u64);
+        if self.min_width > 0 || self.min_height > 0 || self.aspect_ratio.is_some() {
+            let (width, height) = (dimension("width")?, dimension("height")?);
+
+            if width < self.min_width as u64 || height < self.min_height as u64 {
+                return None;
+            }
+            if let Some(range) = &self.aspect_ratio
+                && (height == 0 || !range.contains(&(width as f32 / height as f32)))
+            {
+                return None;
+            }
+        }
+
+        if !self.extensions.is_empty() {
+            let extension = extension(url)?;
+            if !self.extensions.iter().any(|allowed| {
+                allowed
+                    .trim_start_matches('.')
+                    .eq_ignore_ascii_case(extension)
+            }) {
+                return None;
+            }
+        }
+
+        Some(url)
+    }
+}
+
+/// Extension of the last path segment of `url`, without query or fragment.
+fn extension(url: &str) -> Option<&str> {
+    let path = url.split(['?', '#']).next()?;
+    let name = path.rsplit('/').next()?;
+
+    name.rsplit_once('.').map(|(_, extension)| extension)
+}
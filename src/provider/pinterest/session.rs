@@ -0,0 +1,153 @@
+use crate::provider::FetchBackgroundError;
+use log::info;
+use rand::Rng;
+use reqwest::Client;
+use reqwest::header::{COOKIE, HeaderMap, HeaderName, HeaderValue, SET_COOKIE};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+/// How [`PinterestProvider`](super::PinterestProvider) talks to Pinterest, set with the
+/// `session` table of its config and overridden by the `PINTEREST_BASE_URL`,
+/// `PINTEREST_APP_VERSION`, `PINTEREST_CSRF_TOKEN` and `PINTEREST_SESSION` environment variables.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct PinterestSession {
+    /// Scheme and host the searches go to, e.g. a local mock server.
+    pub base_url: String,
+    /// Web app version sent as `X-APP-VERSION`.
+    pub app_version: String,
+    pub user_agent: String,
+    /// Sent both as header and as cookie, taken from the landing page when missing.
+    pub csrf_token: Option<String>,
+    /// Value of the `_pinterest_sess` cookie of a logged in session, searching works without.
+    pub session_cookie: Option<String>,
+    /// Additional headers, replacing the built-in ones with the same name.
+    pub headers: BTreeMap<String, String>,
+}
+
+impl Default for PinterestSession {
+    fn default() -> Self {
+        Self {
+            base_url: "https://www.pinterest.com".into(),
+            app_version: "0dd1dca".into(),
+            user_agent: "Mozilla/5.0 (X11; Linux x86_64; rv:143.0) Gecko/20100101 Firefox/143.0"
+                .into(),
+            csrf_token: None,
+            session_cookie: None,
+            headers: BTreeMap::new(),
+        }
+    }
+}
+
+impl PinterestSession {
+    /// Overrides the fields with the environment variables that are set.
+    pub fn with_env(mut self) -> Self {
+        let var = |name: &str| std::env::var(name).ok().filter(|value| !value.is_empty());
+
+        if let Some(base_url) = var("PINTEREST_BASE_URL") {
+            self.base_url = base_url;
+        }
+        if let Some(app_version) = var("PINTEREST_APP_VERSION") {
+            self.app_version = app_version;
+        }
+        if let Some(token) = var("PINTEREST_CSRF_TOKEN") {
+            self.csrf_token = Some(token);
+        }
+        if let Some(session) = var("PINTEREST_SESSION") {
+            self.session_cookie = Some(session);
+        }
+
+        self
+    }
+
+    pub(crate) fn url(&self, path: &str) -> String {
+        format!("{}{path}", self.base_url.trim_end_matches('/'))
+    }
+
+    /// Requests the landing page for the `csrftoken` cookie it sets.
+    pub(crate) async fn bootstrap_csrf(
+        &self,
+        client: &Client,
+    ) -> Result<String, FetchBackgroundError> {
+        info!("Fetching a CSRF token from {}", self.base_url);
+
+        let response = client
+            .get(self.url("/"))
+            .header("User-Agent", &self.user_agent)
+            .send()
+            .await?;
+        let response = FetchBackgroundError::check_status(response)?;
+
+        response
+            .headers()
+            .get_all(SET_COOKIE)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .filter_map(|cookie| cookie.split(';').next()?.trim().strip_prefix("csrftoken="))
+            .find(|token| !token.is_empty())
+            .map(str::to_owned)
+            .ok_or_else(|| {
+                FetchBackgroundError::Session(
+                    "the landing page didn't set a csrftoken cookie".into(),
+                )
+            })
+    }
+
+    /// Headers of a search request from the page at `source_url`.
+    pub(crate) fn search_headers(
+        &self,
+        csrf_token: &str,
+        source_url: &str,
+    ) -> Result<HeaderMap, FetchBackgroundError> {
+        let mut rng = rand::rng();
+        let trace_id = format!("{:016x}", rng.random::<u64>());
+        let span_id = format!("{:016x}", rng.random::<u64>());
+
+        let mut cookie = format!("csrftoken={csrf_token}; _auth=0");
+        if let Some(session) = &self.session_cookie {
+            cookie.push_str(&format!("; _pinterest_sess={session}"));
+        }
+
+        let base = self.base_url.trim_end_matches('/');
+        let referer = format!("{base}/");
+        let headers = [
+            ("User-Agent", self.user_agent.as_str()),
+            ("Accept", "application/json, text/javascript, */*, q=0.01"),
+            ("Referer", &referer),
+            ("Origin", base),
+            ("X-Requested-With", "XMLHttpRequest"),
+            ("X-APP-VERSION", &self.app_version),
+            ("Content-Type", "application/x-www-form-urlencoded"),
+            ("X-CSRFToken", csrf_token),
+            ("X-Pinterest-AppState", "background"),
+            ("X-Pinterest-Source-Url", source_url),
+            ("X-Pinterest-PWS-Handler", "www/search/[scope].js"),
+            ("screen-dpr", "1"),
+            ("X-B3-TraceId", &trace_id),
+            ("X-B3-SpanId", &span_id),
+            ("X-B3-ParentSpanId", &trace_id),
+            ("X-B3-Flags", "0"),
+            ("Sec-GPC", "1"),
+            ("Sec-Fetch-Dest", "empty"),
+            ("Sec-Fetch-Mode", "cors"),
+            ("Sec-Fetch-Site", "same-origin"),
+            ("Pragma", "no-cache"),
+            ("Cache-Control", "no-cache"),
+            (COOKIE.as_str(), &cookie),
+        ];
+
+        let mut map = HeaderMap::new();
+        let extra = self.headers.iter().map(|(k, v)| (k.as_str(), v.as_str()));
+
+        for (name, value) in headers.into_iter().chain(extra) {
+            let invalid = |err: &dyn std::fmt::Display| {
+                FetchBackgroundError::Session(format!("invalid header {name}: {err}"))
+            };
+            let name = HeaderName::try_from(name).map_err(|err| invalid(&err))?;
+            let value = HeaderValue::try_from(value).map_err(|err| invalid(&err))?;
+            map.insert(name, value);
+        }
+
+        Ok(map)
+    }
+}
@@ -0,0 +1,35 @@
+use crate::provider::persist::write_atomic;
+use serde::{Deserialize, Serialize};
+use std::io::ErrorKind;
+use std::path::Path;
+
+/// Where the searches of a [`PinterestProvider`](super::PinterestProvider) left off, so a later
+/// run continues with the next pages instead of starting over.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PinterestState {
+    pub queries: Vec<QueryState>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueryState {
+    pub query: String,
+    /// Bookmark of the next search page, `None` starts over at the first one.
+    pub bookmark: Option<String>,
+    /// URLs found but not downloaded yet, the last one is the next to download.
+    pub images: Vec<String>,
+}
+
+impl PinterestState {
+    /// The state saved in `path`, `None` if there is no such file.
+    pub fn load(path: &Path) -> std::io::Result<Option<Self>> {
+        match std::fs::read(path) {
+            Ok(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        write_atomic(path, &serde_json::to_vec(self)?)
+    }
+}
@@ -0,0 +1,225 @@
+use crate::provider::{Background, BackgroundProvider, ErrorClass, FetchBackgroundError};
+use futures::future::BoxFuture;
+use log::{error, info, warn};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::time::Duration;
+use tokio::sync::mpsc::error::TryRecvError;
+use tokio::sync::{Mutex, mpsc};
+use tokio::task::JoinHandle;
+use tokio::time::sleep;
+
+type Fetched = Result<Background, FetchBackgroundError>;
+
+/// The calls a [`PrefetchProvider`] passes on to the wrapped provider, boxed so the type of the
+/// wrapped provider doesn't leak into the [`PrefetchProvider`].
+trait Wrapped: Send + Sync {
+    fn mark_used<'a>(&'a self, source: &'a str) -> BoxFuture<'a, ()>;
+
+    fn save_state(&self) -> BoxFuture<'_, ()>;
+
+    fn cycles(&self) -> bool;
+}
+
+impl<P: BackgroundProvider + Send + Sync> Wrapped for P {
+    fn mark_used<'a>(&'a self, source: &'a str) -> BoxFuture<'a, ()> {
+        Box::pin(BackgroundProvider::mark_used(self, source))
+    }
+
+    fn save_state(&self) -> BoxFuture<'_, ()> {
+        Box::pin(BackgroundProvider::save_state(self))
+    }
+
+    fn cycles(&self) -> bool {
+        BackgroundProvider::cycles(self)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PrefetchOptions {
+    /// How many fetched backgrounds are kept ready.
+    pub capacity: usize,
+    /// How many backgrounds are fetched at the same time.
+    pub workers: usize,
+    /// Pause of a worker after a failed fetch.
+    pub retry_delay: Duration,
+    /// How many backgrounds are fetched at most, e.g. as many as are needed. Failed fetches
+    /// count too, their errors answer a request as well.
+    pub limit: Option<usize>,
+}
+
+impl Default for PrefetchOptions {
+    fn default() -> Self {
+        Self {
+            capacity: 8,
+            workers: 4,
+            retry_delay: Duration::from_secs(1),
+            limit: None,
+        }
+    }
+}
+
+/// Counters of a [`PrefetchProvider`] since it was created.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PrefetchStats {
+    /// Requests answered right away with a background that was ready.
+    pub hits: u64,
+    /// Requests that had to wait for a worker.
+    pub waits: u64,
+    /// Fetches of the wrapped provider that failed, their errors are passed on.
+    pub failures: u64,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    hits: AtomicU64,
+    waits: AtomicU64,
+    failures: AtomicU64,
+}
+
+/// Fetches backgrounds from the wrapped provider in the background, so they are already
+/// downloaded and decoded when requested.
+///
+/// Workers stop once the wrapped provider runs out of images or fails with a
+/// [`ErrorClass::Fatal`] error, which is passed on, or once [`PrefetchOptions::limit`]
+/// backgrounds were fetched. After that and once the ready backgrounds
+/// are used up every request fails with [`FetchBackgroundError::NoImages`].
+/// Must be created inside a Tokio runtime.
+pub struct PrefetchProvider {
+    provider: Arc<dyn Wrapped>,
+    ready: Mutex<mpsc::Receiver<Fetched>>,
+    counters: Arc<Counters>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl PrefetchProvider {
+    pub fn new<P>(provider: P, options: PrefetchOptions) -> Self
+    where
+        P: BackgroundProvider + Send + Sync + 'static,
+    {
+        let (sender, receiver) = mpsc::channel(options.capacity.max(1));
+        let provider = Arc::new(provider);
+        let counters = Arc::new(Counters::default());
+        let stopped = Arc::new(AtomicBool::new(false));
+        let remaining = Arc::new(AtomicUsize::new(options.limit.unwrap_or(usize::MAX)));
+
+        let workers = (0..options.workers.max(1))
+            .map(|worker| {
+                let provider = provider.clone();
+                let counters = counters.clone();
+                let sender = sender.clone();
+                let stopped = stopped.clone();
+                let remaining = remaining.clone();
+                let retry_delay = options.retry_delay;
+
+                tokio::spawn(async move {
+                    while !stopped.load(Ordering::Relaxed) {
+                        let taken =
+                            remaining.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| {
+                                n.checked_sub(1)
+                            });
+                        if taken.is_err() {
+                            info!("Prefetch worker {worker} fetched all backgrounds needed");
+                            return;
+                        }
+
+                        let fetched = provider.fetch_background().await;
+
+                        match &fetched {
+                            Ok(_) => {}
+                            Err(FetchBackgroundError::NoImages) => {
+                                info!("Prefetch worker {worker} ran out of images");
+                                return;
+                            }
+                            Err(err) if err.class() == ErrorClass::Fatal => {
+                                error!("Prefetching stopped, worker {worker} failed: {err}");
+                                counters.failures.fetch_add(1, Ordering::Relaxed);
+                                stopped.store(true, Ordering::Relaxed);
+                                let _ = sender.send(fetched).await;
+                                return;
+                            }
+                            Err(err) => {
+                                warn!("Prefetch worker {worker} failed to fetch: {err}");
+                                counters.failures.fetch_add(1, Ordering::Relaxed);
+                            }
+                        }
+
+                        let failed = fetched.is_err();
+
+                        if sender.send(fetched).await.is_err() {
+                            return;
+                        }
+
+                        if failed {
+                            sleep(retry_delay).await;
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        Self {
+            provider,
+            ready: Mutex::new(receiver),
+            counters,
+            workers,
+        }
+    }
+
+    pub fn stats(&self) -> PrefetchStats {
+        PrefetchStats {
+            hits: self.counters.hits.load(Ordering::Relaxed),
+            waits: self.counters.waits.load(Ordering::Relaxed),
+            failures: self.counters.failures.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl std::fmt::Debug for PrefetchProvider {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PrefetchProvider")
+            .field("ready", &self.ready)
+            .field("counters", &self.counters)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Drop for PrefetchProvider {
+    fn drop(&mut self) {
+        for worker in &self.workers {
+            worker.abort();
+        }
+    }
+}
+
+impl BackgroundProvider for PrefetchProvider {
+    async fn fetch_background(&self) -> Result<Background, FetchBackgroundError> {
+        let mut ready = self.ready.lock().await;
+
+        let fetched = match ready.try_recv() {
+            Ok(fetched) => {
+                self.counters.hits.fetch_add(1, Ordering::Relaxed);
+                Some(fetched)
+            }
+            Err(TryRecvError::Empty) => {
+                self.counters.waits.fetch_add(1, Ordering::Relaxed);
+                ready.recv().await
+            }
+            Err(TryRecvError::Disconnected) => None,
+        };
+
+        fetched.unwrap_or(Err(FetchBackgroundError::NoImages))
+    }
+
+    async fn mark_used(&self, source: &str) {
+        self.provider.mark_used(source).await;
+    }
+
+    async fn save_state(&self) {
+        self.provider.save_state().await;
+    }
+
+    fn cycles(&self) -> bool {
+        self.provider.cycles()
+    }
+}
@@ -0,0 +1,108 @@
+use crate::provider::{Background, BackgroundProvider, ErrorClass, FetchBackgroundError};
+use log::warn;
+use rand::Rng;
+use std::time::Duration;
+use tokio::time::sleep;
+
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Fetches per request, including the first one.
+    pub max_attempts: u32,
+    /// Backoff after the first transient failure, doubled after every further one.
+    pub base_delay: Duration,
+    /// Longest backoff, also caps the `Retry-After` of servers.
+    pub max_delay: Duration,
+    /// Fraction of the backoff that is randomized, so concurrent requests spread out.
+    pub jitter: f64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+            jitter: 0.5,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Backoff before the attempt following `failures` transient failures.
+    fn backoff(&self, failures: u32) -> Duration {
+        let exponential = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(failures.saturating_sub(1)))
+            .min(self.max_delay);
+        let jitter = self.jitter.clamp(0.0, 1.0);
+
+        exponential.mul_f64(1.0 - jitter * rand::rng().random::<f64>())
+    }
+}
+
+/// Retries the fetches of the wrapped provider according to the [`ErrorClass`] of their errors:
+/// transient ones after an exponential backoff or the `Retry-After` the server asked for,
+/// per-image ones right away and fatal ones not at all.
+#[derive(Debug)]
+pub struct RetryProvider<P> {
+    provider: P,
+    policy: RetryPolicy,
+}
+
+impl<P> RetryProvider<P> {
+    pub fn new(provider: P, policy: RetryPolicy) -> Self {
+        Self { provider, policy }
+    }
+}
+
+impl<P: BackgroundProvider + Sync> BackgroundProvider for RetryProvider<P> {
+    async fn fetch_background(&self) -> Result<Background, FetchBackgroundError> {
+        let mut transient_failures = 0;
+        let mut attempt = 1;
+
+        loop {
+            let err = match self.provider.fetch_background().await {
+                Ok(background) => return Ok(background),
+                Err(err) => err,
+            };
+
+            let class = err.class();
+
+            if class == ErrorClass::Fatal || attempt >= self.policy.max_attempts {
+                return Err(err);
+            }
+
+            match class {
+                ErrorClass::Transient => {
+                    transient_failures += 1;
+                    // Servers may ask for hours, which would stall the whole run
+                    let delay = err
+                        .retry_after()
+                        .map(|delay| delay.min(self.policy.max_delay))
+                        .unwrap_or_else(|| self.policy.backoff(transient_failures));
+
+                    warn!(
+                        "Fetch attempt {attempt} failed: {err}, retrying in {:.1} seconds",
+                        delay.as_secs_f32()
+                    );
+                    sleep(delay).await;
+                }
+                _ => warn!("Fetch attempt {attempt} failed: {err}, trying another image"),
+            }
+
+            attempt += 1;
+        }
+    }
+
+    async fn mark_used(&self, source: &str) {
+        self.provider.mark_used(source).await;
+    }
+
+    async fn save_state(&self) {
+        self.provider.save_state().await;
+    }
+
+    fn cycles(&self) -> bool {
+        self.provider.cycles()
+    }
+}
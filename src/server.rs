@@ -0,0 +1,455 @@
+use crate::crop::Crop;
+use crate::manifest::Manifest;
+use crate::output::{SaveError, encode_png, encode_webp};
+use crate::provider::{Background, BackgroundProvider, FetchBackgroundError};
+use crate::transformation::{ImageTransformation, Pipeline, TransformContext, TransformError};
+use crate::{ResizeMode, Size, resize_to};
+use axum::Router;
+use axum::body::Bytes;
+use axum::extract::{DefaultBodyLimit, Query, State};
+use axum::http::header::InvalidHeaderValue;
+use axum::http::header::{CONTENT_TYPE, HeaderName};
+use axum::http::{HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use image::DynamicImage;
+use log::{error, info};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use strum_macros::{Display, EnumString};
+use thiserror::Error;
+use tokio::net::TcpListener;
+use tokio::sync::Semaphore;
+use tokio::task::spawn_blocking;
+
+/// Name of the preset used when a request doesn't ask for one.
+pub const DEFAULT_PRESET: &str = "default";
+
+const SEED_HEADER: HeaderName = HeaderName::from_static("x-dreamcore-seed");
+const SOURCE_HEADER: HeaderName = HeaderName::from_static("x-dreamcore-source");
+const MANIFEST_HEADER: HeaderName = HeaderName::from_static("x-dreamcore-manifest");
+
+#[derive(Debug, Error)]
+pub enum ServerError {
+    #[error("Invalid request: {0}")]
+    BadRequest(String),
+
+    #[error("Unknown preset {0:?}")]
+    UnknownPreset(String),
+
+    #[error("No background provider is configured")]
+    NoProvider,
+
+    #[error("Failed to fetch background: {0}")]
+    Fetch(#[from] FetchBackgroundError),
+
+    #[error("Failed to decode the uploaded image: {0}")]
+    Decode(image::ImageError),
+
+    #[error("Transformation error: {0}")]
+    Transform(#[from] TransformError),
+
+    #[error("Encoding error: {0}")]
+    Encode(#[from] SaveError),
+
+    #[error("Worker failed: {0}")]
+    Join(#[from] tokio::task::JoinError),
+
+    #[error("Invalid response header: {0}")]
+    Header(#[from] InvalidHeaderValue),
+}
+
+impl IntoResponse for ServerError {
+    fn into_response(self) -> Response {
+        let status = match self {
+            ServerError::BadRequest(_) | ServerError::Decode(_) => StatusCode::BAD_REQUEST,
+            ServerError::UnknownPreset(_) => StatusCode::NOT_FOUND,
+            ServerError::NoProvider => StatusCode::SERVICE_UNAVAILABLE,
+            ServerError::Fetch(_) => StatusCode::BAD_GATEWAY,
+            ServerError::Transform(_)
+            | ServerError::Encode(_)
+            | ServerError::Join(_)
+            | ServerError::Header(_) => {
+                error!("{self}");
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        };
+
+        (status, self.to_string()).into_response()
+    }
+}
+
+/// Encoding of the returned images. Only PNGs carry the manifest inside the file,
+/// the headers of the response have it either way.
+#[derive(Debug, Clone, Copy, Default, Deserialize, EnumString, Display)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum ImageFormat {
+    #[default]
+    Png,
+    Webp,
+}
+
+impl ImageFormat {
+    fn content_type(self) -> &'static str {
+        match self {
+            ImageFormat::Png => "image/png",
+            ImageFormat::Webp => "image/webp",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ServerOptions {
+    /// Size of generated images when the request doesn't set one.
+    pub size: Size,
+    pub resize_mode: ResizeMode,
+    pub crop: Crop,
+    /// How many images are transformed at the same time, further requests wait for a slot.
+    pub concurrency: usize,
+    /// Largest accepted upload of `POST /transform`, in bytes.
+    pub max_upload: usize,
+    /// Largest size a request may ask for, in either dimension.
+    pub max_size: Size,
+}
+
+impl Default for ServerOptions {
+    fn default() -> Self {
+        Self {
+            size: Size {
+                width: 512,
+                height: 512,
+            },
+            resize_mode: ResizeMode::default(),
+            crop: Crop::default(),
+            concurrency: 4,
+            max_upload: 32 * 1024 * 1024,
+            max_size: Size {
+                width: 4096,
+                height: 4096,
+            },
+        }
+    }
+}
+
+/// Query parameters shared by both endpoints.
+#[derive(Debug, Deserialize)]
+struct ImageQuery {
+    seed: Option<u64>,
+    size: Option<String>,
+    preset: Option<String>,
+    #[serde(default)]
+    format: ImageFormat,
+}
+
+impl ImageQuery {
+    fn size(&self, max: Size) -> Result<Option<Size>, ServerError> {
+        let Some(size) = self.size.as_deref() else {
+            return Ok(None);
+        };
+
+        let size: Size = size.parse().map_err(ServerError::BadRequest)?;
+
+        if size.width > max.width || size.height > max.height {
+            return Err(ServerError::BadRequest(format!(
+                "size {}x{} is larger than {}x{}",
+                size.width, size.height, max.width, max.height
+            )));
+        }
+
+        Ok(Some(size))
+    }
+
+    fn context(&self) -> TransformContext {
+        match self.seed {
+            Some(seed) => TransformContext::new(seed),
+            None => TransformContext::from_random_seed(),
+        }
+    }
+}
+
+/// Serves images made by named [`Pipeline`] presets over HTTP:
+///
+/// - `GET /generate?seed=&size=&preset=&format=` transforms a background from the provider.
+/// - `POST /transform?seed=&size=&preset=&format=` transforms the image in the request body,
+///   resizing it only when `size` is given.
+///
+/// Responses carry the seed, the source and the manifest of the image in the
+/// `X-Dreamcore-Seed`, `X-Dreamcore-Source` and `X-Dreamcore-Manifest` headers.
+pub struct Server<P> {
+    presets: HashMap<String, Arc<Pipeline>>,
+    provider: Option<Arc<P>>,
+    options: ServerOptions,
+    slots: Semaphore,
+}
+
+impl<P: BackgroundProvider + Send + Sync + 'static> Server<P> {
+    /// A server with `pipeline` as the [`DEFAULT_PRESET`] and no provider, so only
+    /// `POST /transform` works until [`Server::with_provider`] is called.
+    pub fn new(pipeline: Pipeline, options: ServerOptions) -> Self {
+        Self {
+            presets: HashMap::from([(DEFAULT_PRESET.to_string(), Arc::new(pipeline))]),
+            provider: None,
+            slots: Semaphore::new(options.concurrency.max(1)),
+            options,
+        }
+    }
+
+    pub fn with_preset(mut self, name: impl Into<String>, pipeline: Pipeline) -> Self {
+        self.presets.insert(name.into(), Arc::new(pipeline));
+        self
+    }
+
+    pub fn with_provider(mut self, provider: P) -> Self {
+        self.provider = Some(Arc::new(provider));
+        self
+    }
+
+    pub fn router(self) -> Router {
+        let max_upload = self.options.max_upload;
+
+        Router::new()
+            .route("/generate", get(generate::<P>))
+            .route("/transform", post(transform::<P>))
+            .layer(DefaultBodyLimit::max(max_upload))
+            .with_state(Arc::new(self))
+    }
+
+    /// Serves on `addr` until the process receives Ctrl-C.
+    pub async fn serve(self, addr: SocketAddr) -> std::io::Result<()> {
+        let listener = TcpListener::bind(addr).await?;
+        info!("Listening on http://{}", listener.local_addr()?);
+
+        axum::serve(listener, self.router())
+            .with_graceful_shutdown(async {
+                let _ = tokio::signal::ctrl_c().await;
+                info!("Shutting down");
+            })
+            .await
+    }
+
+    fn preset(&self, name: Option<&str>) -> Result<Arc<Pipeline>, ServerError> {
+        let name = name.unwrap_or(DEFAULT_PRESET);
+
+        self.presets
+            .get(name)
+            .cloned()
+            .ok_or_else(|| ServerError::UnknownPreset(name.to_string()))
+    }
+
+    /// Decodes, resizes and transforms the image on the blocking pool once a slot is free.
+    async fn render(
+        &self,
+        input: Input,
+        pipeline: Arc<Pipeline>,
+        mut ctx: TransformContext,
+        size: Option<Size>,
+        format: ImageFormat,
+    ) -> Result<Response, ServerError> {
+        let _slot = self
+            .slots
+            .acquire()
+            .await
+            .expect("the semaphore is never closed");
+
+        let (resize_mode, crop) = (self.options.resize_mode, self.options.crop);
+
+        let (bytes, manifest) = spawn_blocking(move || -> Result<_, ServerError> {
+            let (mut image, source) = input.decode()?;
+
+            if let Some(size) = size {
+                resize_to(&mut image, size, resize_mode, crop, &mut ctx.rng);
+            }
+
+            info!("Transforming {source} with seed {}", ctx.seed());
+            pipeline.transform(&mut image, &mut ctx)?;
+
+            let manifest = Manifest::new(&ctx, Some(source), &image);
+            let bytes = match format {
+                ImageFormat::Png => encode_png(&image, &manifest)?,
+                ImageFormat::Webp => encode_webp(&image)?,
+            };
+
+            Ok((bytes, manifest))
+        })
+        .await??;
+
+        image_response(bytes, &manifest, format)
+    }
+}
+
+/// What [`Server::render`] works on, uploads are only decoded once they got a slot.
+enum Input {
+    Background(Background),
+    Upload(Bytes),
+}
+
+impl Input {
+    fn decode(self) -> Result<(DynamicImage, String), ServerError> {
+        match self {
+            Input::Background(Background { image, source, .. }) => Ok((image, source)),
+            Input::Upload(body) => {
+                let image = image::load_from_memory(&body).map_err(ServerError::Decode)?;
+                Ok((image, "upload".to_string()))
+            }
+        }
+    }
+}
+
+async fn generate<P: BackgroundProvider + Send + Sync + 'static>(
+    State(server): State<Arc<Server<P>>>,
+    Query(query): Query<ImageQuery>,
+) -> Result<Response, ServerError> {
+    let pipeline = server.preset(query.preset.as_deref())?;
+    let size = query
+        .size(server.options.max_size)?
+        .unwrap_or(server.options.size);
+    let provider = server.provider.as_ref().ok_or(ServerError::NoProvider)?;
+
+    let background = provider.fetch_background().await?;
+    let source = background.source.clone();
+
+    let response = server
+        .render(
+            Input::Background(background),
+            pipeline,
+            query.context(),
+            Some(size),
+            query.format,
+        )
+        .await?;
+
+    provider.mark_used(&source).await;
+    Ok(response)
+}
+
+async fn transform<P: BackgroundProvider + Send + Sync + 'static>(
+    State(server): State<Arc<Server<P>>>,
+    Query(query): Query<ImageQuery>,
+    body: Bytes,
+) -> Result<Response, ServerError> {
+    let pipeline = server.preset(query.preset.as_deref())?;
+    let size = query.size(server.options.max_size)?;
+
+    if body.is_empty() {
+        return Err(ServerError::BadRequest("the request body is empty".into()));
+    }
+
+    server
+        .render(
+            Input::Upload(body),
+            pipeline,
+            query.context(),
+            size,
+            query.format,
+        )
+        .await
+}
+
+fn image_response(
+    bytes: Vec<u8>,
+    manifest: &Manifest,
+    format: ImageFormat,
+) -> Result<Response, ServerError> {
+    let json = serde_json::to_string(manifest).map_err(SaveError::from)?;
+    let source = manifest.source.as_deref().unwrap_or_default();
+
+    let headers = [
+        (
+            CONTENT_TYPE,
+            HeaderValue::from_static(format.content_type()),
+        ),
+        (
+            SEED_HEADER,
+            HeaderValue::try_from(manifest.seed.to_string())?,
+        ),
+        (
+            SOURCE_HEADER,
+            HeaderValue::try_from(percent_encode_non_ascii(source))?,
+        ),
+        (MANIFEST_HEADER, HeaderValue::try_from(escape_json(&json))?),
+    ];
+
+    Ok((headers, bytes).into_response())
+}
+
+/// Percent-encodes the bytes of everything but visible ASCII, like browsers do with URLs.
+fn percent_encode_non_ascii(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+
+    for byte in value.bytes() {
+        if byte.is_ascii_graphic() || byte == b' ' {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{byte:02X}"));
+        }
+    }
+
+    encoded
+}
+
+/// Replaces everything but visible ASCII and spaces in serialized JSON with `\uXXXX` escapes,
+/// the result is still the same JSON. Compact JSON only has such characters inside strings,
+/// e.g. `DEL` or non-ASCII text of user supplied phrases.
+fn escape_json(json: &str) -> String {
+    let mut escaped = String::with_capacity(json.len());
+
+    for c in json.chars() {
+        if c.is_ascii_graphic() || c == ' ' {
+            escaped.push(c);
+        } else {
+            for unit in c.encode_utf16(&mut [0; 2]) {
+                escaped.push_str(&format!("\\u{unit:04x}"));
+            }
+        }
+    }
+
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{Value, json};
+
+    fn query(size: &str) -> ImageQuery {
+        ImageQuery {
+            seed: None,
+            size: Some(size.to_string()),
+            preset: None,
+            format: ImageFormat::default(),
+        }
+    }
+
+    #[test]
+    fn sizes_above_the_maximum_are_bad_requests() {
+        let max = ServerOptions::default().max_size;
+
+        assert_eq!(
+            query("4096x1024").size(max).unwrap(),
+            Some(Size {
+                width: 4096,
+                height: 1024
+            })
+        );
+        assert!(matches!(
+            query("4097x1024").size(max),
+            Err(ServerError::BadRequest(_))
+        ));
+        assert!(matches!(
+            query("100000").size(max),
+            Err(ServerError::BadRequest(_))
+        ));
+    }
+
+    #[test]
+    fn escaped_json_is_a_valid_header_with_the_same_value() {
+        let value = json!({ "text": "Exit?\u{7f} \u{1b}[0m Ausgang? 出口 👁" });
+        let escaped = escape_json(&value.to_string());
+
+        assert!(HeaderValue::try_from(escaped.as_str()).is_ok());
+        assert_eq!(serde_json::from_str::<Value>(&escaped).unwrap(), value);
+    }
+}
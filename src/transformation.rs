@@ -1,29 +1,258 @@
+pub mod control;
 pub mod distortion;
 pub mod eyes;
+pub mod placement;
 pub mod text;
 
 use std::fmt::{Display, Formatter};
 use image::DynamicImage;
-use std::ops::Add;
-use log::info;
+use std::ops::{Add, Range};
+use log::{info, warn};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use serde::{Deserialize, Deserializer};
+use serde_json::Value;
+use thiserror::Error;
+use crate::assets::AssetError;
+use crate::manifest::StepRecord;
+use crate::transformation::control::{Chance, OneOf, Repeat};
+use crate::transformation::distortion::Distortion;
+use crate::transformation::eyes::Eyeballs;
+use crate::transformation::placement::OccupancyMap;
+use crate::transformation::text::DreamcoreStyledTextTransform;
+
+/// Position of the frame being rendered within a looping animation.
+#[derive(Debug, Clone, Copy)]
+pub struct Frame {
+    pub index: u32,
+    pub count: u32,
+}
+
+impl Frame {
+    /// Progress through the loop in `0.0..1.0`.
+    pub fn time(&self) -> f32 {
+        self.index as f32 / self.count.max(1) as f32
+    }
+}
+
+/// Per-image state handed down by [`Pipeline`] to every step.
+///
+/// All randomness of a transformation must be drawn from [`TransformContext::rng`],
+/// so the same seed applied to the same background always yields the same output.
+///
+/// When rendering an animation every frame starts from a clone of the same context,
+/// so `rng` makes the same decisions in every frame, while [`TransformContext::motion_rng`]
+/// differs from frame to frame.
+///
+/// Steps that draw objects claim their area in the [`OccupancyMap`], so later steps can avoid it.
+#[derive(Clone)]
+pub struct TransformContext {
+    seed: u64,
+    pub rng: StdRng,
+    frame: Option<Frame>,
+    frame_rng: Option<StdRng>,
+    records: Vec<StepRecord>,
+    occupancy: OccupancyMap,
+}
+
+impl TransformContext {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            rng: StdRng::seed_from_u64(seed),
+            frame: None,
+            frame_rng: None,
+            records: Vec::new(),
+            occupancy: OccupancyMap::default(),
+        }
+    }
+
+    /// Turns the context into the context of the given animation frame.
+    pub fn with_frame(mut self, frame: Frame) -> Self {
+        let frame_seed = (frame.index as u64 + 1).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+        self.frame_rng = Some(StdRng::seed_from_u64(self.seed ^ frame_seed));
+        self.frame = Some(frame);
+        self
+    }
+
+    /// The frame being rendered, `None` for still images.
+    pub fn frame(&self) -> Option<Frame> {
+        self.frame
+    }
+
+    /// Source of randomness that is re-rolled every animation frame, e.g. for noise or flicker.
+    ///
+    /// Falls back to [`TransformContext::rng`] for still images.
+    pub fn motion_rng(&mut self) -> &mut StdRng {
+        self.frame_rng.as_mut().unwrap_or(&mut self.rng)
+    }
+
+    /// Creates a context with a freshly drawn seed.
+    pub fn from_random_seed() -> Self {
+        Self::new(rand::random())
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Remembers a decision made by `step`, so it ends up in the image's [`Manifest`](crate::manifest::Manifest).
+    pub fn record(&mut self, step: &dyn Display, details: Value) {
+        self.records.push(StepRecord {
+            step: step.to_string(),
+            details,
+        });
+    }
+
+    pub fn records(&self) -> &[StepRecord] {
+        &self.records
+    }
+
+    pub fn occupancy(&self) -> &OccupancyMap {
+        &self.occupancy
+    }
+
+    pub fn occupancy_mut(&mut self) -> &mut OccupancyMap {
+        &mut self.occupancy
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum TransformError {
+    #[error("Invalid asset pack: {0}")]
+    Assets(#[from] AssetError),
+
+    #[error("No {0} assets available")]
+    MissingAssets(&'static str),
+
+    #[error("Invalid parameter: {0}")]
+    InvalidParameter(String),
+
+    #[error("Cannot transform an empty {0}x{1} image")]
+    EmptyImage(u32, u32),
+
+    #[error("Step #{index} ({step}) failed: {source}")]
+    Step {
+        index: usize,
+        step: String,
+        #[source]
+        source: Box<TransformError>,
+    },
+}
+
+/// Fails with [`TransformError::EmptyImage`] if the image has no pixels.
+pub fn ensure_not_empty(image: &DynamicImage) -> Result<(), TransformError> {
+    if image.width() == 0 || image.height() == 0 {
+        return Err(TransformError::EmptyImage(image.width(), image.height()));
+    }
+
+    Ok(())
+}
 
 /// # Requirements
 ///
-/// - The input image **must be square**, meaning:
-///   [`DynamicImage::width()`] == [`DynamicImage::height()`].
+/// - The input image may have any width and height, implementations must not
+///   assume a square image and should size their elements relative to the shorter side.
 /// - Implementations should operate directly on the provided [`DynamicImage`]
 ///   without changing its dimensions.
 ///
 /// # Parameters
 ///
 /// - `image`: A mutable reference to a [`DynamicImage`] that will be transformed.
+/// - `ctx`: The [`TransformContext`] of the image, the only allowed source of randomness.
+///
+/// # Errors
+///
+/// A failing transformation may leave the image partially transformed,
+/// [`Pipeline`] takes care of restoring it when the failure is not fatal.
 pub trait ImageTransformation: Send + Sync + Display {
-    fn transform(&self, image: &mut DynamicImage);
+    fn transform(
+        &self,
+        image: &mut DynamicImage,
+        ctx: &mut TransformContext,
+    ) -> Result<(), TransformError>;
+}
+
+/// What [`Pipeline`] does when one of its steps fails.
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(tag = "policy", rename_all = "snake_case")]
+pub enum FailurePolicy {
+    /// Stop and report the failing step.
+    #[default]
+    Abort,
+    /// Undo the failing step and carry on with the next one.
+    Skip,
+    /// Undo the failing step and run it again, up to `attempts` more times, then abort.
+    Retry { attempts: u32 },
 }
 
 #[derive(Default)]
 pub struct Pipeline {
     steps: Vec<Box<dyn ImageTransformation>>,
+    on_failure: FailurePolicy,
+}
+
+impl Pipeline {
+    pub fn with_failure_policy(mut self, policy: FailurePolicy) -> Self {
+        self.on_failure = policy;
+        self
+    }
+
+    fn run_step(
+        &self,
+        index: usize,
+        step: &dyn ImageTransformation,
+        image: &mut DynamicImage,
+        ctx: &mut TransformContext,
+    ) -> Result<(), TransformError> {
+        let attempts = match self.on_failure {
+            FailurePolicy::Abort => {
+                return step
+                    .transform(image, ctx)
+                    .map_err(|e| step_error(index, step, e));
+            }
+            FailurePolicy::Skip => 0,
+            FailurePolicy::Retry { attempts } => attempts,
+        };
+
+        let snapshot = image.clone();
+        let recorded = ctx.records.len();
+        let claimed = ctx.occupancy.regions().len();
+
+        for attempt in 0..=attempts {
+            match step.transform(image, ctx) {
+                Ok(()) => return Ok(()),
+                Err(err) => {
+                    *image = snapshot.clone();
+                    ctx.records.truncate(recorded);
+                    ctx.occupancy.truncate(claimed);
+
+                    if attempt < attempts {
+                        warn!("Step #{index} ({step}) failed: {err}, retrying");
+                    } else if matches!(self.on_failure, FailurePolicy::Skip) {
+                        warn!("Step #{index} ({step}) failed: {err}, skipping it");
+                        return Ok(());
+                    } else {
+                        return Err(step_error(index, step, err));
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn step_error(
+    index: usize,
+    step: &dyn ImageTransformation,
+    source: TransformError,
+) -> TransformError {
+    TransformError::Step {
+        index,
+        step: step.to_string(),
+        source: Box::new(source),
+    }
 }
 
 impl Add<Box<dyn ImageTransformation>> for Pipeline {
@@ -44,6 +273,53 @@ impl<T: ImageTransformation + 'static> Add<T> for Pipeline {
     }
 }
 
+/// A single pipeline step as written in a config file, tagged by its `type`.
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub(crate) enum Step {
+    Text(DreamcoreStyledTextTransform),
+    Distortion(Distortion<Range<f32>>),
+    Eyeballs(Eyeballs),
+    Chance(Chance),
+    OneOf(OneOf),
+    Repeat(Repeat),
+}
+
+impl From<Step> for Box<dyn ImageTransformation> {
+    fn from(step: Step) -> Self {
+        match step {
+            Step::Text(t) => Box::new(t),
+            Step::Distortion(t) => Box::new(t),
+            Step::Eyeballs(t) => Box::new(t),
+            Step::Chance(t) => Box::new(t),
+            Step::OneOf(t) => Box::new(t),
+            Step::Repeat(t) => Box::new(t),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Pipeline {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        struct Helper {
+            steps: Vec<Step>,
+            #[serde(default)]
+            on_failure: FailurePolicy,
+        }
+
+        let raw = Helper::deserialize(deserializer)?;
+
+        let pipeline = Pipeline::default().with_failure_policy(raw.on_failure);
+
+        Ok(raw.steps.into_iter().fold(pipeline, |pipeline, step| {
+            pipeline + Box::<dyn ImageTransformation>::from(step)
+        }))
+    }
+}
+
 impl Display for Pipeline {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(f, "Pipeline")
@@ -51,17 +327,28 @@ impl Display for Pipeline {
 }
 
 impl ImageTransformation for Pipeline {
-    fn transform(&self, image: &mut DynamicImage) {
+    fn transform(
+        &self,
+        image: &mut DynamicImage,
+        ctx: &mut TransformContext,
+    ) -> Result<(), TransformError> {
+        ensure_not_empty(image)?;
+
         let chain = self.steps
             .iter()
             .map(|s| s.to_string())
             .collect::<Vec<_>>()
             .join(" -> ");
 
-        info!("Running transformations for image {image:p}: {chain}",);
+        info!(
+            "Running transformations for image {image:p} with seed {}: {chain}",
+            ctx.seed()
+        );
 
-        for step in &self.steps {
-            step.transform(image)
+        for (index, step) in self.steps.iter().enumerate() {
+            self.run_step(index, step.as_ref(), image, ctx)?;
         }
+
+        Ok(())
     }
 }
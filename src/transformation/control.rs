@@ -0,0 +1,266 @@
+use crate::transformation::{ImageTransformation, Step, TransformContext, TransformError};
+use crate::weight::{default_weight, valid_weights};
+use image::DynamicImage;
+use rand::Rng;
+use rand::distr::weighted::WeightedIndex;
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer};
+use serde_json::json;
+use std::fmt::{Display, Formatter};
+use std::ops::RangeInclusive;
+
+/// Runs the wrapped step with the given probability, e.g. "apply text 60% of the time".
+pub struct Chance {
+    probability: f64,
+    step: Box<dyn ImageTransformation>,
+}
+
+impl Chance {
+    pub fn new(probability: f64, step: impl ImageTransformation + 'static) -> Self {
+        Self {
+            probability,
+            step: Box::new(step),
+        }
+    }
+}
+
+/// Runs exactly one of its steps, picked according to their weights.
+#[derive(Default)]
+pub struct OneOf {
+    choices: Vec<(f64, Box<dyn ImageTransformation>)>,
+}
+
+impl OneOf {
+    /// Adds a step that is picked with a chance proportional to `weight`.
+    pub fn or(mut self, weight: f64, step: impl ImageTransformation + 'static) -> Self {
+        self.choices.push((weight, Box::new(step)));
+        self
+    }
+}
+
+/// Runs the wrapped step a random number of times.
+pub struct Repeat {
+    times: RangeInclusive<u32>,
+    step: Box<dyn ImageTransformation>,
+}
+
+impl Repeat {
+    pub fn new(times: RangeInclusive<u32>, step: impl ImageTransformation + 'static) -> Self {
+        Self {
+            times,
+            step: Box::new(step),
+        }
+    }
+}
+
+impl Display for Chance {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Chance({:.0}%, {})", self.probability * 100.0, self.step)
+    }
+}
+
+impl Display for OneOf {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let choices = self
+            .choices
+            .iter()
+            .map(|(_, s)| s.to_string())
+            .collect::<Vec<_>>()
+            .join(" | ");
+
+        write!(f, "OneOf({choices})")
+    }
+}
+
+impl Display for Repeat {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Repeat({:?}, {})", self.times, self.step)
+    }
+}
+
+impl ImageTransformation for Chance {
+    fn transform(
+        &self,
+        image: &mut DynamicImage,
+        ctx: &mut TransformContext,
+    ) -> Result<(), TransformError> {
+        if !(0.0..=1.0).contains(&self.probability) {
+            return Err(TransformError::InvalidParameter(format!(
+                "chance probability {} is outside of 0..=1",
+                self.probability
+            )));
+        }
+
+        let applied = ctx.rng.random_bool(self.probability);
+        ctx.record(self, json!({ "applied": applied }));
+
+        if applied {
+            self.step.transform(image, ctx)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl ImageTransformation for OneOf {
+    fn transform(
+        &self,
+        image: &mut DynamicImage,
+        ctx: &mut TransformContext,
+    ) -> Result<(), TransformError> {
+        if self.choices.is_empty() {
+            return Ok(());
+        }
+
+        let weights = WeightedIndex::new(self.choices.iter().map(|(weight, _)| *weight))
+            .map_err(|err| TransformError::InvalidParameter(format!("one_of weights: {err}")))?;
+        let index = ctx.rng.sample(&weights);
+
+        let step = &self.choices[index].1;
+        ctx.record(self, json!({ "chosen": index, "step": step.to_string() }));
+
+        step.transform(image, ctx)
+    }
+}
+
+impl ImageTransformation for Repeat {
+    fn transform(
+        &self,
+        image: &mut DynamicImage,
+        ctx: &mut TransformContext,
+    ) -> Result<(), TransformError> {
+        if self.times.is_empty() {
+            return Ok(());
+        }
+
+        let times = ctx.rng.random_range(self.times.clone());
+        ctx.record(self, json!({ "times": times }));
+
+        for _ in 0..times {
+            self.step.transform(image, ctx)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<'de> Deserialize<'de> for Chance {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        struct Helper {
+            probability: f64,
+            step: Step,
+        }
+
+        let raw = Helper::deserialize(deserializer)?;
+        if !(0.0..=1.0).contains(&raw.probability) {
+            return Err(D::Error::custom(format!(
+                "chance probability {} is outside of 0..=1",
+                raw.probability
+            )));
+        }
+
+        Ok(Chance {
+            probability: raw.probability,
+            step: raw.step.into(),
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for OneOf {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        struct Choice {
+            #[serde(default = "default_weight")]
+            weight: f64,
+            step: Step,
+        }
+
+        #[derive(Deserialize)]
+        struct Helper {
+            choices: Vec<Choice>,
+        }
+
+        let raw = Helper::deserialize(deserializer)?;
+        if raw.choices.is_empty() {
+            return Err(D::Error::custom("one_of needs at least one choice"));
+        }
+        if !valid_weights(raw.choices.iter().map(|c| c.weight)) {
+            return Err(D::Error::custom(
+                "one_of weights must be finite, non-negative and not all zero",
+            ));
+        }
+
+        Ok(OneOf {
+            choices: raw
+                .choices
+                .into_iter()
+                .map(|c| (c.weight, c.step.into()))
+                .collect(),
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for Repeat {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        struct Helper {
+            times: RangeInclusive<u32>,
+            step: Step,
+        }
+
+        let raw = Helper::deserialize(deserializer)?;
+
+        Ok(Repeat {
+            times: raw.times,
+            step: raw.step.into(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STEP: &str = r#"{ "type": "distortion", "intensity": { "start": 1.0, "end": 2.0 } }"#;
+
+    #[test]
+    fn out_of_range_probabilities_fail_to_parse() {
+        let chance = |probability: f64| {
+            let json = format!(r#"{{ "probability": {probability}, "step": {STEP} }}"#);
+            serde_json::from_str::<Chance>(&json)
+        };
+
+        assert!(chance(0.0).is_ok());
+        assert!(chance(0.6).is_ok());
+        assert!(chance(1.0).is_ok());
+        assert!(chance(-0.1).is_err());
+        assert!(chance(1.5).is_err());
+    }
+
+    #[test]
+    fn one_of_needs_choices_with_usable_weights() {
+        let one_of = |weights: &[f64]| {
+            let choices = weights
+                .iter()
+                .map(|weight| format!(r#"{{ "weight": {weight}, "step": {STEP} }}"#))
+                .collect::<Vec<_>>()
+                .join(", ");
+            serde_json::from_str::<OneOf>(&format!(r#"{{ "choices": [{choices}] }}"#))
+        };
+
+        assert!(one_of(&[3.0, 0.0]).is_ok());
+        assert!(one_of(&[]).is_err());
+        assert!(one_of(&[0.0, 0.0]).is_err());
+        assert!(one_of(&[1.0, -1.0]).is_err());
+    }
+}
@@ -1,47 +1,325 @@
+pub mod perlin;
+
 use std::fmt::{Display, Formatter};
-use crate::transformation::ImageTransformation;
+use crate::transformation::distortion::perlin::Perlin;
+use crate::transformation::{ImageTransformation, TransformContext, TransformError};
 use derive_new::new;
-use image::{DynamicImage, GenericImage, GenericImageView, Rgba};
+use image::DynamicImage;
 use log::info;
-use rand::{Rng, rng};
+use rand::Rng;
 use rand::distr::uniform::SampleRange;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use strum_macros::Display;
 
-#[derive(Debug, new)]
+/// Adds noise to every pixel, the strength of the [`Noise`] model is scaled by `intensity`.
+///
+/// Works on an RGBA8 buffer, so images in other formats are converted first.
+#[derive(Debug, new, Deserialize)]
 pub struct Distortion<R> {
     intensity: R,
+    #[new(default)]
+    #[serde(default)]
+    noise: Noise,
+}
+
+impl<R> Distortion<R> {
+    pub fn with_noise(mut self, noise: Noise) -> Self {
+        self.noise = noise;
+        self
+    }
+}
+
+/// How the noise of a [`Distortion`] looks, amplitudes are in 0-255 channel values at an
+/// intensity of `1.0`.
+#[derive(Debug, Clone, Serialize, Deserialize, Display)]
+#[serde(tag = "model", rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum Noise {
+    /// Independent uniform noise of up to `amplitude` on every color channel.
+    Uniform {
+        #[serde(default = "default_amplitude")]
+        amplitude: f32,
+    },
+    /// Independent normally distributed noise on every color channel.
+    Gaussian {
+        #[serde(default = "default_sigma")]
+        sigma: f32,
+    },
+    /// Uniform noise of up to `amplitude` that only changes the brightness, not the hue.
+    Monochrome {
+        #[serde(default = "default_amplitude")]
+        amplitude: f32,
+    },
+    /// Turns about `density` of the pixels black or white.
+    SaltAndPepper {
+        #[serde(default = "default_density")]
+        density: f32,
+    },
+    /// Smooth bright and dark patches, the largest about `scale` pixels wide.
+    Blotches {
+        #[serde(default = "default_scale")]
+        scale: f32,
+        #[serde(default = "default_blotch_amplitude")]
+        amplitude: f32,
+        #[serde(default = "default_octaves")]
+        octaves: u32,
+    },
+    /// Monochrome normally distributed grain, growing with the square root of the luminance
+    /// like shot noise, so it's `sigma` in white areas and fades out in the shadows.
+    FilmGrain {
+        #[serde(default = "default_sigma")]
+        sigma: f32,
+    },
+}
+
+fn default_amplitude() -> f32 {
+    15.0
+}
+
+fn default_sigma() -> f32 {
+    8.0
+}
+
+fn default_density() -> f32 {
+    0.02
+}
+
+fn default_scale() -> f32 {
+    96.0
+}
+
+fn default_blotch_amplitude() -> f32 {
+    20.0
+}
+
+fn default_octaves() -> u32 {
+    3
+}
+
+impl Default for Noise {
+    fn default() -> Self {
+        Noise::Uniform {
+            amplitude: default_amplitude(),
+        }
+    }
+}
+
+impl Noise {
+    fn validate(&self) -> Result<(), TransformError> {
+        let invalid = |what: &str, value: f32| {
+            Err(TransformError::InvalidParameter(format!(
+                "{self} noise {what} {value} is out of range"
+            )))
+        };
+
+        match *self {
+            Noise::Uniform { amplitude }
+            | Noise::Monochrome { amplitude }
+            | Noise::Blotches { amplitude, .. }
+                if !(amplitude.is_finite() && amplitude >= 0.0) =>
+            {
+                invalid("amplitude", amplitude)
+            }
+            Noise::Gaussian { sigma } | Noise::FilmGrain { sigma }
+                if !(sigma.is_finite() && sigma >= 0.0) =>
+            {
+                invalid("sigma", sigma)
+            }
+            Noise::SaltAndPepper { density } if !(0.0..=1.0).contains(&density) => {
+                invalid("density", density)
+            }
+            Noise::Blotches { scale, .. } if !(scale.is_finite() && scale > 0.0) => {
+                invalid("scale", scale)
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Wyrand, much cheaper than going through `Rng::random_range` for every channel.
+struct RowNoise(u64);
+
+impl RowNoise {
+    /// Every row gets its own stream, so the output doesn't depend on how rows are scheduled.
+    fn new(seed: u64, row: u32) -> Self {
+        let mut state = seed ^ (row as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+        // A round of SplitMix64 so neighbouring rows don't start from similar states
+        state = (state ^ (state >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        state = (state ^ (state >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        Self(state ^ (state >> 31))
+    }
+
+    #[inline(always)]
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0xA076_1D64_78BD_642F);
+        let t = (self.0 as u128).wrapping_mul((self.0 ^ 0xE703_7ED1_A0B4_28DB) as u128);
+        ((t >> 64) ^ t) as u64
+    }
+
+    /// Uniform in `-1.0..1.0`.
+    #[inline(always)]
+    fn signed(&mut self) -> f32 {
+        (self.next() >> 40) as u32 as f32 / (1 << 23) as f32 - 1.0
+    }
+
+    /// Approximately standard normal, the sum of four uniforms from a single draw.
+    #[inline(always)]
+    fn gaussian(&mut self) -> f32 {
+        let bits = self.next();
+        let sum = (0..4)
+            .map(|i| ((bits >> (i * 16)) & 0xFFFF) as u32)
+            .sum::<u32>();
+
+        // Four uniforms in 0..1 have a mean of 2 and a variance of 1/3
+        (sum as f32 / 65536.0 - 2.0) * 3.0f32.sqrt()
+    }
+}
+
+#[inline(always)]
+fn add_offset(channel: &mut u8, offset: f32) {
+    *channel = (*channel as f32 + offset).clamp(0.0, 255.0) as u8;
+}
+
+/// Applies uniform noise to one row of RGBA pixels, 21 random bits per color channel.
+///
+/// Works in 8.8 fixed point, converting the random bits to floats costs more than the rest.
+#[inline(always)]
+fn uniform_row(row: &mut [u8], noise: &mut RowNoise, amplitude: f32) {
+    const BITS: u32 = 21;
+    const MASK: u64 = (1 << BITS) - 1;
+
+    // Past 2^16 nearly every channel ends up at 0 or 255 anyway, and the products below stay
+    // far from overflowing however large the intensity gets
+    let amplitude = (amplitude.min(65536.0) * 256.0) as i64;
+
+    for pixel in row.chunks_exact_mut(4) {
+        let bits = noise.next();
+
+        for (i, channel) in pixel[..3].iter_mut().enumerate() {
+            let sample = ((bits >> (i as u32 * BITS)) & MASK) as i64;
+            let offset = ((sample * 2 * amplitude) >> BITS) - amplitude;
+            let value = ((*channel as i64) << 8) + offset;
+            *channel = (value.clamp(0, 255 << 8) >> 8) as u8;
+        }
+    }
+}
+
+/// Applies `model` to row `y` of RGBA pixels, `perlin` is only needed by [`Noise::Blotches`].
+fn distort_row(
+    model: &Noise,
+    row: &mut [u8],
+    y: u32,
+    noise: &mut RowNoise,
+    intensity: f32,
+    perlin: Option<&Perlin>,
+) {
+    match *model {
+        Noise::Uniform { amplitude } => uniform_row(row, noise, amplitude * intensity),
+        Noise::Gaussian { sigma } => {
+            for pixel in row.chunks_exact_mut(4) {
+                for channel in &mut pixel[..3] {
+                    add_offset(channel, noise.gaussian() * sigma * intensity);
+                }
+            }
+        }
+        Noise::Monochrome { amplitude } => {
+            for pixel in row.chunks_exact_mut(4) {
+                let offset = noise.signed() * amplitude * intensity;
+                pixel[..3].iter_mut().for_each(|c| add_offset(c, offset));
+            }
+        }
+        Noise::SaltAndPepper { density } => {
+            let threshold = ((density * intensity).min(1.0) as f64 * u32::MAX as f64) as u64;
+
+            for pixel in row.chunks_exact_mut(4) {
+                let bits = noise.next();
+
+                if bits & 0xFFFF_FFFF < threshold {
+                    let value = if bits >> 63 == 1 { 255 } else { 0 };
+                    pixel[..3].fill(value);
+                }
+            }
+        }
+        Noise::Blotches {
+            scale,
+            amplitude,
+            octaves,
+        } => {
+            let Some(perlin) = perlin else { return };
+
+            for (x, pixel) in row.chunks_exact_mut(4).enumerate() {
+                let value = perlin.fractal(x as f32 / scale, y as f32 / scale, octaves);
+                let offset = value * amplitude * intensity;
+                pixel[..3].iter_mut().for_each(|c| add_offset(c, offset));
+            }
+        }
+        Noise::FilmGrain { sigma } => {
+            for pixel in row.chunks_exact_mut(4) {
+                let luma =
+                    (0.299 * pixel[0] as f32 + 0.587 * pixel[1] as f32 + 0.114 * pixel[2] as f32)
+                        / 255.0;
+                let offset = noise.gaussian() * sigma * intensity * luma.sqrt();
+                pixel[..3].iter_mut().for_each(|c| add_offset(c, offset));
+            }
+        }
+    }
 }
 
 impl<R: SampleRange<f32>> Display for Distortion<R> {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "Distortion")
+        write!(f, "Distortion({})", self.noise)
     }
 }
 
 impl<R: SampleRange<f32> + Send + Sync + Clone> ImageTransformation for Distortion<R> {
-    fn transform(&self, image: &mut DynamicImage) {
-        let (width, height) = image.dimensions();
-        let mut rng = rng();
-        let intensity = rng.random_range(self.intensity.clone());
+    fn transform(
+        &self,
+        image: &mut DynamicImage,
+        ctx: &mut TransformContext,
+    ) -> Result<(), TransformError> {
+        if self.intensity.is_empty() {
+            return Err(TransformError::InvalidParameter(
+                "distortion intensity range is empty".into(),
+            ));
+        }
 
-        info!("Applying distortion for image {image:p} with {intensity:.2} intensity");
+        self.noise.validate()?;
 
-        for x in 0..width {
-            for y in 0..height {
-                let pixel = image.get_pixel(x, y);
-                let channels = pixel.0;
+        let intensity = ctx.rng.random_range(self.intensity.clone());
+        ctx.record(self, json!({ "intensity": intensity, "noise": self.noise }));
 
-                let new_pixel = Rgba([
-                    (channels[0] as f32 + rng.random_range(-15.0..15.0) * intensity)
-                        .clamp(0.0, 255.0) as u8,
-                    (channels[1] as f32 + rng.random_range(-15.0..15.0) * intensity)
-                        .clamp(0.0, 255.0) as u8,
-                    (channels[2] as f32 + rng.random_range(-15.0..15.0) * intensity)
-                        .clamp(0.0, 255.0) as u8,
-                    channels[3],
-                ]);
+        // The noise is re-rolled for every frame of an animation
+        let rng = ctx.motion_rng();
+        let seed = rng.random();
+        let perlin = matches!(self.noise, Noise::Blotches { .. }).then(|| Perlin::new(rng));
 
-                image.put_pixel(x, y, new_pixel);
-            }
+        info!("Applying distortion for image {image:p} with {intensity:.2} intensity");
+
+        // Free for images that already are RGBA8, which is what backgrounds are resized to
+        let mut buffer = std::mem::take(image).into_rgba8();
+        let row_len = buffer.width() as usize * 4;
+
+        if row_len > 0 {
+            buffer
+                .par_chunks_mut(row_len)
+                .enumerate()
+                .for_each(|(y, row)| {
+                    let mut noise = RowNoise::new(seed, y as u32);
+                    distort_row(
+                        &self.noise,
+                        row,
+                        y as u32,
+                        &mut noise,
+                        intensity,
+                        perlin.as_ref(),
+                    )
+                });
         }
+
+        *image = DynamicImage::ImageRgba8(buffer);
+
+        Ok(())
     }
 }
@@ -0,0 +1,80 @@
+use rand::Rng;
+use rand::seq::SliceRandom;
+
+/// 2D gradient noise, smooth values in roughly `-1.0..=1.0` that change over about one unit.
+pub struct Perlin {
+    permutation: [u8; 512],
+}
+
+impl Perlin {
+    pub fn new(rng: &mut impl Rng) -> Self {
+        let mut values = (0..=255).collect::<Vec<u8>>();
+        values.shuffle(rng);
+
+        let mut permutation = [0; 512];
+        for (i, p) in permutation.iter_mut().enumerate() {
+            *p = values[i & 255];
+        }
+
+        Self { permutation }
+    }
+
+    pub fn get(&self, x: f32, y: f32) -> f32 {
+        let (x0, y0) = (x.floor(), y.floor());
+        let (xf, yf) = (x - x0, y - y0);
+        let xi = (x0 as i64 & 255) as usize;
+        let yi = (y0 as i64 & 255) as usize;
+
+        let p = &self.permutation;
+        let corner = |dx: usize, dy: usize| p[p[xi + dx] as usize + yi + dy];
+
+        let (u, v) = (fade(xf), fade(yf));
+        let bottom = lerp(
+            u,
+            gradient(corner(0, 0), xf, yf),
+            gradient(corner(1, 0), xf - 1.0, yf),
+        );
+        let top = lerp(
+            u,
+            gradient(corner(0, 1), xf, yf - 1.0),
+            gradient(corner(1, 1), xf - 1.0, yf - 1.0),
+        );
+
+        lerp(v, bottom, top)
+    }
+
+    /// Sums `octaves` layers of noise, each twice as fine and half as strong as the previous one.
+    pub fn fractal(&self, x: f32, y: f32, octaves: u32) -> f32 {
+        let (mut total, mut norm) = (0.0, 0.0);
+        let (mut frequency, mut amplitude) = (1.0, 1.0);
+
+        for _ in 0..octaves {
+            total += self.get(x * frequency, y * frequency) * amplitude;
+            norm += amplitude;
+            frequency *= 2.0;
+            amplitude *= 0.5;
+        }
+
+        if norm > 0.0 { total / norm } else { 0.0 }
+    }
+}
+
+#[inline(always)]
+fn fade(t: f32) -> f32 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+#[inline(always)]
+fn lerp(t: f32, a: f32, b: f32) -> f32 {
+    a + t * (b - a)
+}
+
+#[inline(always)]
+fn gradient(hash: u8, x: f32, y: f32) -> f32 {
+    match hash & 3 {
+        0 => x + y,
+        1 => -x + y,
+        2 => x - y,
+        _ => -x - y,
+    }
+}
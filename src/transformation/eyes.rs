@@ -1,18 +1,25 @@
 use std::fmt::{Display, Formatter};
-use crate::assets;
-use crate::transformation::ImageTransformation;
+use crate::assets::{AssetPack, ImageAsset};
+use crate::transformation::placement::{PlacementOptions, Placer};
+use crate::transformation::{
+    Frame, ImageTransformation, TransformContext, TransformError, ensure_not_empty,
+};
 use image::imageops::{FilterType, overlay, resize};
 use image::{DynamicImage, Rgba};
 use imageproc::definitions::Image;
 use imageproc::geometric_transformations::{Interpolation, rotate_about_center};
-use include_dir::Dir;
 use rand::seq::IndexedRandom;
-use rand::{Rng, rng};
+use rand::Rng;
 use std::ops::RangeInclusive;
+use std::path::PathBuf;
+use std::sync::Arc;
 use log::info;
+use serde::{Deserialize, Deserializer};
+use serde_json::{Value, json};
 use strum_macros::Display;
 
-#[derive(Display)]
+#[derive(Display, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum Eyeball {
     SimpleEye,
     EyeWithWings,
@@ -21,14 +28,18 @@ pub enum Eyeball {
 pub struct Eyeballs {
     r#type: Eyeball,
     count: RangeInclusive<u32>,
-    balls: Vec<DynamicImage>,
-    wings: Option<Vec<DynamicImage>>,
+    pack: Arc<AssetPack>,
+    placement: PlacementOptions,
 }
 
+/// Where the eye sits on a wing when its asset has no anchor.
+const DEFAULT_ANCHOR: (f32, f32) = (0.5, 0.5);
+
 #[inline(always)]
 fn scale_and_rotate(image: &DynamicImage, scale: f32, angle_deg: Option<f32>) -> Image<Rgba<u8>> {
-    let new_width = (image.width() as f32 * scale) as u32;
-    let new_height = (image.height() as f32 * scale) as u32;
+    // Eyes scaled to tiny backgrounds would round down to nothing, which rotating can't handle
+    let new_width = ((image.width() as f32 * scale) as u32).max(1);
+    let new_height = ((image.height() as f32 * scale) as u32).max(1);
     let resized_ball = resize(image, new_width, new_height, FilterType::Lanczos3);
 
     if let Some(angle_deg) = angle_deg
@@ -45,69 +56,167 @@ fn scale_and_rotate(image: &DynamicImage, scale: f32, angle_deg: Option<f32>) ->
     }
 }
 
-fn place_simple_ball(ball: &DynamicImage, image: &mut DynamicImage) {
-    let mut rng = rng();
+/// How an eye is displaced in the current animation frame.
+#[derive(Clone, Copy)]
+struct Motion {
+    dx: i64,
+    dy: i64,
+    /// Vertical scale of the eye, below `1.0` while blinking.
+    squash: f32,
+}
 
-    let rotated_ball = scale_and_rotate(
-        ball,
-        rng.random_range(0.9..=1.2),
-        Some(rng.random_range(-40.0..=40.0)),
-    );
+impl Motion {
+    const STILL: Motion = Motion {
+        dx: 0,
+        dy: 0,
+        squash: 1.0,
+    };
 
-    let max_x = image.width().saturating_sub(rotated_ball.width());
-    let max_y = image.height().saturating_sub(rotated_ball.height());
-    let x = rng.random_range(0..=max_x);
-    let y = rng.random_range(0..=max_y);
+    /// Slow circular drift plus a short blink once per loop, `phase` and `blink_at` in `0.0..1.0`.
+    fn at(frame: Frame, image: &DynamicImage, phase: f32, blink_at: f32) -> Self {
+        let amplitude = image.width().min(image.height()) as f32 * 0.02;
+        let angle = (frame.time() + phase) * std::f32::consts::TAU;
+        let blinking = (frame.time() - blink_at).rem_euclid(1.0) < 0.1;
 
-    overlay(image, &rotated_ball, x as _, y as _);
+        Motion {
+            dx: (angle.cos() * amplitude).round() as i64,
+            dy: (angle.sin() * amplitude).round() as i64,
+            squash: if blinking { 0.1 } else { 1.0 },
+        }
+    }
 }
 
-fn place_ball_with_wing(wing: &DynamicImage, ball: &DynamicImage, image: &mut DynamicImage) {
-    let mut rng = rng();
+fn overlay_eye(image: &mut DynamicImage, eye: &Image<Rgba<u8>>, x: u32, y: u32, motion: Motion) {
+    let (x, y) = (x as i64 + motion.dx, y as i64 + motion.dy);
+
+    if motion.squash < 1.0 {
+        let height = ((eye.height() as f32 * motion.squash) as u32).max(1);
+        let squashed = resize(eye, eye.width(), height, FilterType::Triangle);
+        overlay(image, &squashed, x, y + (eye.height() - height) as i64 / 2);
+    } else {
+        overlay(image, eye, x, y);
+    }
+}
+
+fn place_simple_ball(
+    ball: &DynamicImage,
+    image: &mut DynamicImage,
+    rng: &mut impl Rng,
+    placer: &mut Placer,
+    motion: Motion,
+) -> Result<Value, TransformError> {
+    let scale = rng.random_range(0.9..=1.2);
+    let angle = rng.random_range(-40.0..=40.0);
+    let rotated_ball = scale_and_rotate(ball, scale, Some(angle));
+
+    let (x, y) = placer.place(rng, image, rotated_ball.dimensions())?;
 
-    let scaled_wing = scale_and_rotate(wing, rng.random_range(0.9..=1.2), None);
+    overlay_eye(image, &rotated_ball, x, y, motion);
 
-    let max_x = image.width().saturating_sub(scaled_wing.width());
-    let max_y = image.height().saturating_sub(scaled_wing.height());
-    let wing_x = rng.random_range(0..=max_x);
-    let wing_y = rng.random_range(0..=max_y);
+    Ok(json!({ "x": x, "y": y, "scale": scale, "angle": angle }))
+}
+
+fn place_ball_with_wing(
+    wing: &DynamicImage,
+    anchor: (f32, f32),
+    ball: &DynamicImage,
+    image: &mut DynamicImage,
+    rng: &mut impl Rng,
+    placer: &mut Placer,
+    motion: Motion,
+) -> Result<Value, TransformError> {
+    let wing_scale = rng.random_range(0.9..=1.2);
+    let scaled_wing = scale_and_rotate(wing, wing_scale, None);
 
-    overlay(image, &scaled_wing, wing_x as _, wing_y as _);
+    // The eye sits on the wing, so the wing's area covers both
+    let (wing_x, wing_y) = placer.place(rng, image, scaled_wing.dimensions())?;
 
-    let rotated_ball = scale_and_rotate(
-        ball,
-        rng.random_range(0.5..=0.8),
-        Some(rng.random_range(-10.0..=10.0)),
+    overlay(
+        image,
+        &scaled_wing,
+        wing_x as i64 + motion.dx,
+        wing_y as i64 + motion.dy,
     );
 
-    let center_x = wing_x + (scaled_wing.width() / 2).saturating_sub(rotated_ball.width() / 2);
-    let center_y = wing_y + (scaled_wing.height() / 2).saturating_sub(rotated_ball.height() / 2);
+    let scale = rng.random_range(0.5..=0.8);
+    let angle = rng.random_range(-10.0..=10.0);
+    let rotated_ball = scale_and_rotate(ball, scale, Some(angle));
+
+    let anchor_x = (scaled_wing.width() as f32 * anchor.0.clamp(0.0, 1.0)) as u32;
+    let anchor_y = (scaled_wing.height() as f32 * anchor.1.clamp(0.0, 1.0)) as u32;
+    let center_x = wing_x + anchor_x.saturating_sub(rotated_ball.width() / 2);
+    let center_y = wing_y + anchor_y.saturating_sub(rotated_ball.height() / 2);
 
-    overlay(image, &rotated_ball, center_x as _, center_y as _);
+    overlay_eye(image, &rotated_ball, center_x, center_y, motion);
+
+    Ok(json!({
+        "x": center_x,
+        "y": center_y,
+        "scale": scale,
+        "angle": angle,
+        "wing": { "x": wing_x, "y": wing_y, "scale": wing_scale },
+    }))
 }
 
 impl Eyeballs {
-    pub fn new(r#type: Eyeball, count: RangeInclusive<u32>) -> Self {
-        let balls: Vec<DynamicImage> = load_images(assets::EYEBALLS);
-
-        let mut wings: Option<Vec<DynamicImage>> = None;
+    /// Uses the eyeballs and wings embedded in the binary.
+    pub fn new(r#type: Eyeball, count: RangeInclusive<u32>) -> Result<Self, TransformError> {
+        Self::with_pack(r#type, count, AssetPack::shared_embedded()?)
+    }
 
-        assert!(!balls.is_empty(), "at least one eyeball is required");
+    pub fn with_pack(
+        r#type: Eyeball,
+        count: RangeInclusive<u32>,
+        pack: Arc<AssetPack>,
+    ) -> Result<Self, TransformError> {
+        if pack.eyeballs.is_empty() {
+            return Err(TransformError::MissingAssets("eyeball"));
+        }
 
-        if matches!(r#type, Eyeball::EyeWithWings) {
-            wings = Some(load_images(assets::WINGS));
-            assert!(
-                unsafe { !&wings.as_ref().unwrap_unchecked().is_empty() },
-                "at least one wing image is required when using EyeBall::EyeWithWings"
-            );
+        if matches!(r#type, Eyeball::EyeWithWings) && pack.wings.is_empty() {
+            return Err(TransformError::MissingAssets("wing"));
         }
 
-        Self {
+        Ok(Self {
             r#type,
             count,
-            balls,
-            wings,
+            pack,
+            placement: PlacementOptions::default(),
+        })
+    }
+
+    pub fn with_placement(mut self, placement: PlacementOptions) -> Self {
+        self.placement = placement;
+        self
+    }
+}
+
+impl<'de> Deserialize<'de> for Eyeballs {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        struct Helper {
+            eyeball: Eyeball,
+            count: RangeInclusive<u32>,
+            pack: Option<PathBuf>,
+            #[serde(default)]
+            placement: PlacementOptions,
         }
+
+        let raw = Helper::deserialize(deserializer)?;
+
+        let eyeballs = match raw.pack {
+            Some(path) => AssetPack::load(path)
+                .map_err(TransformError::from)
+                .and_then(|pack| Eyeballs::with_pack(raw.eyeball, raw.count, Arc::new(pack))),
+            None => Eyeballs::new(raw.eyeball, raw.count),
+        };
+
+        eyeballs
+            .map(|eyeballs| eyeballs.with_placement(raw.placement))
+            .map_err(serde::de::Error::custom)
     }
 }
 
@@ -118,36 +227,94 @@ impl Display for Eyeballs {
 }
 
 impl ImageTransformation for Eyeballs {
-    fn transform(&self, image: &mut DynamicImage) {
-        let mut rng = rng();
+    fn transform(
+        &self,
+        image: &mut DynamicImage,
+        ctx: &mut TransformContext,
+    ) -> Result<(), TransformError> {
+        ensure_not_empty(image)?;
+
+        let frame = ctx.frame();
+        let rng = &mut ctx.rng;
+        let mut placer = Placer::new(&mut ctx.occupancy, &self.placement);
 
         if self.count.is_empty() {
-            return;
+            return Ok(());
         }
 
+        let mut placed = Vec::new();
+
         for _ in 0..rng.random_range(self.count.clone()) {
-            let ball = self.balls.choose(&mut rng).unwrap();
-            let ball = crate::resize_to_background_image_scale(ball, image, 0.2);
+            let ball_asset = choose_asset(&self.pack.eyeballs, rng)?;
+            let ball = crate::resize_to_background_image_scale(&ball_asset.image, image, 0.2);
 
             info!("Applying {} for image {image:p}", self.r#type);
 
-            match self.r#type {
-                Eyeball::SimpleEye => place_simple_ball(&ball, image),
+            // Drawn from the shared rng, so every frame moves the same eye the same way
+            let motion = match frame {
+                Some(frame) => Motion::at(frame, image, rng.random(), rng.random()),
+                None => Motion::STILL,
+            };
+
+            let mut details = match self.r#type {
+                Eyeball::SimpleEye => place_simple_ball(&ball, image, rng, &mut placer, motion)?,
                 Eyeball::EyeWithWings => {
-                    let wing = self.wings.as_ref().unwrap().choose(&mut rng).unwrap();
-                    let wing = crate::resize_to_background_image_scale(wing, image, 0.3);
-                    place_ball_with_wing(&wing, &ball, image)
+                    let wing_asset = choose_asset(&self.pack.wings, rng)?;
+                    let wing =
+                        crate::resize_to_background_image_scale(&wing_asset.image, image, 0.3);
+                    let anchor = wing_asset.anchor.unwrap_or(DEFAULT_ANCHOR);
+                    let mut details = place_ball_with_wing(
+                        &wing,
+                        anchor,
+                        &ball,
+                        image,
+                        rng,
+                        &mut placer,
+                        motion,
+                    )?;
+                    details["wing"]["asset"] = json!(wing_asset.name);
+                    details
                 }
-            }
+            };
+
+            details["asset"] = json!(ball_asset.name);
+            placed.push(details);
         }
+
+        ctx.record(self, json!({ "eyes": placed }));
+
+        Ok(())
     }
 }
 
-#[inline(always)]
-fn load_images(dir: &Dir) -> Vec<DynamicImage> {
-    dir.entries()
-        .iter()
-        .map(|file| file.as_file().expect("valid image files"))
-        .map(|ball| image::load_from_memory(ball.contents()).expect("valid image"))
-        .collect()
+fn choose_asset<'a>(
+    assets: &'a [ImageAsset],
+    rng: &mut impl Rng,
+) -> Result<&'a ImageAsset, TransformError> {
+    assets
+        .choose_weighted(rng, |asset| asset.weight)
+        .map_err(|err| TransformError::InvalidParameter(format!("asset weights: {err}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{GenericImageView, RgbImage};
+
+    #[test]
+    fn tiny_backgrounds_get_eyes_without_panicking() {
+        for (width, height) in [(1, 1), (3, 200), (200, 3)] {
+            for r#type in [Eyeball::SimpleEye, Eyeball::EyeWithWings] {
+                let eyeballs = Eyeballs::new(r#type, 1..=3).unwrap();
+
+                for seed in 0..8 {
+                    let mut image = DynamicImage::ImageRgb8(RgbImage::new(width, height));
+                    let mut ctx = TransformContext::new(seed);
+
+                    eyeballs.transform(&mut image, &mut ctx).unwrap();
+                    assert_eq!(image.dimensions(), (width, height));
+                }
+            }
+        }
+    }
 }
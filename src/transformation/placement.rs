@@ -0,0 +1,175 @@
+use crate::transformation::TransformError;
+use image::{DynamicImage, GenericImageView};
+use rand::Rng;
+use serde::Deserialize;
+
+/// Area of the image claimed by a step, in pixels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Rect {
+    pub fn new(x: u32, y: u32, width: u32, height: u32) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    /// Area shared with `other` once `other` is grown by `spacing` on every side.
+    fn overlap(&self, other: &Rect, spacing: u32) -> u64 {
+        let left = self.x.max(other.x.saturating_sub(spacing));
+        let top = self.y.max(other.y.saturating_sub(spacing));
+        let right = (self.x + self.width).min(other.x + other.width + spacing);
+        let bottom = (self.y + self.height).min(other.y + other.height + spacing);
+
+        right.saturating_sub(left) as u64 * bottom.saturating_sub(top) as u64
+    }
+}
+
+/// How a step positions what it draws relative to the regions claimed before it.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct PlacementOptions {
+    /// Probability of ignoring the claimed regions and allowing an overlap.
+    pub overlap_chance: f64,
+    /// Minimum distance to the claimed regions.
+    pub spacing: u32,
+    /// Minimum distance to the image edges, ignored when the object doesn't fit otherwise.
+    pub margin: u32,
+    /// How many random positions are tried before settling for the least overlapping one.
+    pub attempts: u32,
+}
+
+impl Default for PlacementOptions {
+    fn default() -> Self {
+        Self {
+            overlap_chance: 0.0,
+            spacing: 8,
+            margin: 0,
+            attempts: 32,
+        }
+    }
+}
+
+/// Regions of the image claimed by the steps so far, shared through the
+/// [`TransformContext`](crate::transformation::TransformContext).
+#[derive(Debug, Clone, Default)]
+pub struct OccupancyMap {
+    regions: Vec<Rect>,
+}
+
+impl OccupancyMap {
+    pub fn regions(&self) -> &[Rect] {
+        &self.regions
+    }
+
+    pub fn claim(&mut self, rect: Rect) {
+        self.regions.push(rect);
+    }
+
+    /// Forgets the regions claimed after the first `len`, e.g. by a step that failed.
+    pub(crate) fn truncate(&mut self, len: usize) {
+        self.regions.truncate(len);
+    }
+
+    fn overlap(&self, rect: &Rect, spacing: u32) -> u64 {
+        self.regions.iter().map(|r| rect.overlap(r, spacing)).sum()
+    }
+
+    /// Picks the position of a `width`x`height` object inside `bounds` and claims it.
+    pub fn place(
+        &mut self,
+        rng: &mut impl Rng,
+        bounds: (u32, u32),
+        (width, height): (u32, u32),
+        options: &PlacementOptions,
+    ) -> Result<(u32, u32), TransformError> {
+        if !(0.0..=1.0).contains(&options.overlap_chance) {
+            return Err(TransformError::InvalidParameter(format!(
+                "overlap chance {} is outside of 0..=1",
+                options.overlap_chance
+            )));
+        }
+
+        let range = |bound: u32, size: u32| {
+            let max = bound.saturating_sub(size);
+
+            if max >= options.margin.saturating_mul(2) {
+                options.margin..=max - options.margin
+            } else {
+                0..=max
+            }
+        };
+        let (x_range, y_range) = (range(bounds.0, width), range(bounds.1, height));
+
+        let may_overlap = rng.random_bool(options.overlap_chance);
+        let overlap = |rect: &Rect| {
+            if may_overlap {
+                0
+            } else {
+                self.overlap(rect, options.spacing)
+            }
+        };
+        let mut candidate = || {
+            Rect::new(
+                rng.random_range(x_range.clone()),
+                rng.random_range(y_range.clone()),
+                width,
+                height,
+            )
+        };
+
+        let mut rect = candidate();
+        let mut least = overlap(&rect);
+
+        for _ in 1..options.attempts {
+            if least == 0 {
+                break;
+            }
+
+            let next = candidate();
+            let next_overlap = overlap(&next);
+
+            if next_overlap < least {
+                (rect, least) = (next, next_overlap);
+            }
+        }
+
+        self.claim(rect);
+
+        Ok((rect.x, rect.y))
+    }
+}
+
+/// The occupancy map of an image together with the options of the step placing objects on it.
+pub(crate) struct Placer<'a> {
+    occupancy: &'a mut OccupancyMap,
+    options: &'a PlacementOptions,
+}
+
+impl<'a> Placer<'a> {
+    pub(crate) fn new(occupancy: &'a mut OccupancyMap, options: &'a PlacementOptions) -> Self {
+        Self { occupancy, options }
+    }
+
+    pub(crate) fn place(
+        &mut self,
+        rng: &mut impl Rng,
+        image: &DynamicImage,
+        size: (u32, u32),
+    ) -> Result<(u32, u32), TransformError> {
+        self.occupancy
+            .place(rng, image.dimensions(), size, self.options)
+    }
+
+    pub(crate) fn claim(&mut self, rect: Rect) {
+        self.occupancy.claim(rect);
+    }
+}
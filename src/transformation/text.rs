@@ -1,53 +1,108 @@
+pub mod phrases;
+
 use std::fmt::{Display, Formatter};
-use crate::transformation::ImageTransformation;
-use crate::{assets, layout_paragraph};
-use ab_glyph::{Font, FontRef, PxScale, ScaleFont, point};
+use crate::transformation::{ImageTransformation, TransformContext, TransformError};
+use crate::assets::AssetPack;
+use crate::layout_paragraph;
+use crate::transformation::placement::{PlacementOptions, Placer, Rect};
+use crate::transformation::text::phrases::{PhraseEntry, PhraseFile, Phrases, RenderState};
+use ab_glyph::{Font, FontArc, PxScale, ScaleFont, point};
 use image::{DynamicImage, Rgba};
 use imageproc::drawing::draw_text_mut;
 use num_traits::Num;
 use rand::seq::{IndexedRandom, IteratorRandom};
-use rand::{Rng, rng};
+use rand::Rng;
 use std::ops::{AddAssign, SubAssign};
+use std::path::PathBuf;
+use std::sync::Arc;
 use log::info;
+use serde::{Deserialize, Deserializer};
+use serde_json::{Value, json};
 use strum::IntoEnumIterator;
 use strum_macros::{Display, EnumIter};
 
-pub struct DreamcoreStyledTextTransform<'a> {
-    fonts: Vec<FontRef<'a>>,
-    texts: Vec<&'static str>,
+pub struct DreamcoreStyledTextTransform {
+    pack: Arc<AssetPack>,
+    phrases: Phrases,
+    placement: PlacementOptions,
 }
 
-impl<'a> Default for DreamcoreStyledTextTransform<'a> {
-    fn default() -> Self {
-        let mut fonts = Vec::new();
+impl DreamcoreStyledTextTransform {
+    /// Uses the fonts embedded in the binary.
+    pub fn new() -> Result<Self, TransformError> {
+        Self::with_pack(AssetPack::shared_embedded()?)
+    }
+
+    pub fn with_pack(pack: Arc<AssetPack>) -> Result<Self, TransformError> {
+        if pack.fonts.is_empty() {
+            return Err(TransformError::MissingAssets("font"));
+        }
+
+        Ok(Self {
+            pack,
+            phrases: Phrases::default(),
+            placement: PlacementOptions::default(),
+        })
+    }
+
+    /// Draws these phrases instead of the built-in ones.
+    pub fn with_phrases(mut self, phrases: Phrases) -> Self {
+        self.phrases = phrases;
+        self
+    }
+
+    pub fn with_placement(mut self, placement: PlacementOptions) -> Self {
+        self.placement = placement;
+        self
+    }
+}
 
-        for font in assets::FONTS.entries() {
-            let font = font.as_file().expect(".ttf font file");
-            fonts.push(FontRef::try_from_slice(font.contents()).expect("valid ttf font"));
+impl<'de> Deserialize<'de> for DreamcoreStyledTextTransform {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        struct Helper {
+            pack: Option<PathBuf>,
+            phrases_file: Option<PathBuf>,
+            #[serde(default)]
+            phrases: Vec<PhraseEntry>,
+            #[serde(default)]
+            names: Vec<String>,
+            #[serde(default)]
+            placement: PlacementOptions,
         }
 
-        assert!(
-            !fonts.is_empty(),
-            "fonts cannot be empty, at least one font is required"
-        );
-
-        Self {
-            fonts,
-            texts: vec![
-                "Why do you keep coming back?",
-                "The walls remember you.",
-                "I dreamt of you last night.",
-                "Why do you always return?",
-                "Exit?",
-                "WAKE UP",
-                "This is a Dream",
-                "come with me, dear",
-                "i want to go back",
-                "he is watching!",
-                "It's time to go home",
-                "It's funny!",
-            ],
+        let raw = Helper::deserialize(deserializer)?;
+
+        let transform = match raw.pack {
+            Some(path) => AssetPack::load(path)
+                .map_err(TransformError::from)
+                .and_then(|pack| DreamcoreStyledTextTransform::with_pack(Arc::new(pack))),
+            None => DreamcoreStyledTextTransform::new(),
         }
+        .map_err(serde::de::Error::custom)?
+        .with_placement(raw.placement);
+
+        let mut file = PhraseFile {
+            phrases: raw.phrases,
+            names: raw.names,
+        };
+
+        if let Some(path) = raw.phrases_file {
+            let loaded = PhraseFile::load(path).map_err(serde::de::Error::custom)?;
+            file.phrases.extend(loaded.phrases);
+            file.names.extend(loaded.names);
+        }
+
+        if file.phrases.is_empty() {
+            return Ok(transform);
+        }
+
+        let phrases = Phrases::from_file(file).map_err(serde::de::Error::custom)?;
+
+        Ok(transform.with_phrases(phrases))
     }
 }
 
@@ -63,37 +118,47 @@ enum RepeatedDirection {
 
 enum PlacementStyle {
     Single,
-    Repeated {
-        times: u32,
-        direction: RepeatedDirection,
-    },
+    Repeated(Repetition),
+}
+
+struct Repetition {
+    times: u32,
+    direction: RepeatedDirection,
 }
 
-fn get_random_placement_style() -> PlacementStyle {
-    let mut rng = rng();
+struct TextParams {
+    scale: PxScale,
+    width: f32,
+    height: f32,
+    x: i32,
+    y: i32,
+    color: Rgba<u8>,
+}
 
+fn get_random_placement_style(rng: &mut impl Rng) -> PlacementStyle {
     if rng.random_bool(0.8) {
         PlacementStyle::Single
     } else {
         let times = rng.random_range(2..=10);
 
-        let direction = unsafe {
-            RepeatedDirection::iter()
-                .choose(&mut rng)
-                .unwrap_unchecked()
-        };
+        let direction = RepeatedDirection::iter()
+            .choose(rng)
+            .unwrap_or(RepeatedDirection::Top);
 
-        PlacementStyle::Repeated { times, direction }
+        PlacementStyle::Repeated(Repetition { times, direction })
     }
 }
 
 fn random_text_params(
-    font: &FontRef,
+    font: &FontArc,
     text: &str,
     image: &DynamicImage,
-) -> (PxScale, f32, f32, i32, i32, Rgba<u8>) {
-    let mut rng = rng();
-    let scale = PxScale::from(rng.random_range(28.0..34.0));
+    rng: &mut impl Rng,
+    placer: &mut Placer,
+) -> Result<TextParams, TransformError> {
+    // Sizes are tuned for a 512px image, scale them along with the shorter side
+    let size_factor = image.width().min(image.height()) as f32 / 512.0;
+    let scale = PxScale::from(rng.random_range(28.0..34.0) * size_factor);
     let scaled_font = font.into_scaled(scale);
 
     let mut glyphs = Vec::new();
@@ -113,11 +178,7 @@ fn random_text_params(
         .ceil();
 
     let height = scale.y;
-    let max_x = (image.width() as f32 - width).max(0.0);
-    let max_y = (image.height() as f32 - height).max(0.0);
-
-    let x = rng.random_range(0.0..max_x) as _;
-    let y = rng.random_range(0.0..max_y) as _;
+    let (x, y) = placer.place(rng, image, (width as u32, height.ceil() as u32))?;
 
     let color = Rgba([
         rng.random_range(200..255),
@@ -126,30 +187,71 @@ fn random_text_params(
         rng.random_range(200..255),
     ]);
 
-    (scale, width, height, x, y, color)
+    Ok(TextParams {
+        scale,
+        width,
+        height,
+        x: x as _,
+        y: y as _,
+        color,
+    })
 }
 
-fn draw_random_text(font: &FontRef, text: &str, image: &mut DynamicImage) {
-    let (scale, _, _, x, y, color) = random_text_params(font, text, image);
-    draw_text_mut(image, color, x, y, scale, font, text);
+fn draw_random_text(
+    font: &FontArc,
+    text: &str,
+    image: &mut DynamicImage,
+    rng: &mut impl Rng,
+    placer: &mut Placer,
+    visible: bool,
+) -> Result<Value, TransformError> {
+    let TextParams {
+        scale, x, y, color, ..
+    } = random_text_params(font, text, image, rng, placer)?;
+
+    if visible {
+        draw_text_mut(image, color, x, y, scale, font, text);
+    }
+
+    Ok(json!({ "x": x, "y": y, "size": scale.y, "color": color.0 }))
 }
 
 fn apply_repeated_text(
-    font: &FontRef,
+    font: &FontArc,
     text: &str,
     image: &mut DynamicImage,
-    times: u32,
-    direction: &RepeatedDirection,
-) {
-    let mut rng = rng();
-    let (scale, _, height, mut x, mut y, color) = random_text_params(font, text, image);
+    repetition: &Repetition,
+    rng: &mut impl Rng,
+    placer: &mut Placer,
+    visible: bool,
+) -> Result<Value, TransformError> {
+    let TextParams {
+        scale,
+        width,
+        height,
+        mut x,
+        mut y,
+        color,
+    } = random_text_params(font, text, image, rng, placer)?;
+    let details = json!({ "x": x, "y": y, "size": scale.y, "color": color.0 });
 
-    for _ in 0..times {
+    for i in 0..repetition.times {
         let step = rng.random_range(7..14);
-        
-        draw_text_mut(image, color, x, y, scale, font, text);
-        direction.apply_direction(step, height as i32, &mut x, &mut y);
+
+        if visible {
+            draw_text_mut(image, color, x, y, scale, font, text);
+        }
+
+        // The first copy was claimed when it was placed
+        if i > 0 && x >= 0 && y >= 0 {
+            placer.claim(Rect::new(x as _, y as _, width as _, height.ceil() as _));
+        }
+        repetition
+            .direction
+            .apply_direction(step, height as i32, &mut x, &mut y);
     }
+
+    Ok(details)
 }
 
 impl RepeatedDirection {
@@ -183,30 +285,74 @@ impl RepeatedDirection {
     }
 }
 
-impl Display for DreamcoreStyledTextTransform<'_> {
+impl Display for DreamcoreStyledTextTransform {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(f, "Text")
     }
 }
 
-impl ImageTransformation for DreamcoreStyledTextTransform<'_> {
-    fn transform(&self, image: &mut DynamicImage) {
-        let mut rng = rng();
-        
-        for _ in 0..rng.random_range(1..3) {
-            let font = unsafe { self.fonts.choose(&mut rng).unwrap_unchecked() };
-            let text = unsafe { self.texts.choose(&mut rng).unwrap_unchecked() };
+impl ImageTransformation for DreamcoreStyledTextTransform {
+    fn transform(
+        &self,
+        image: &mut DynamicImage,
+        ctx: &mut TransformContext,
+    ) -> Result<(), TransformError> {
+        let count = ctx.rng.random_range(1..3);
+        // Texts flicker in animations, every frame hides some of them
+        let visible = (0..count)
+            .map(|_| ctx.frame().is_none() || ctx.motion_rng().random_bool(0.85))
+            .collect::<Vec<_>>();
+
+        let rng = &mut ctx.rng;
+        let mut placer = Placer::new(&mut ctx.occupancy, &self.placement);
+        let mut state = RenderState::new(rng);
+        let mut drawn = Vec::new();
 
-            match get_random_placement_style() {
+        for visible in visible {
+            let font = self
+                .pack
+                .fonts
+                .choose_weighted(rng, |font| font.weight)
+                .map_err(|err| TransformError::InvalidParameter(format!("font weights: {err}")))?;
+            let phrase = self
+                .phrases
+                .choose(rng)
+                .ok_or(TransformError::MissingAssets("text"))?;
+            let text = &self.phrases.render(phrase, &mut state, rng);
+
+            let mut details = match get_random_placement_style(rng) {
                 PlacementStyle::Single => {
                     info!("Appending single text for image {image:p}");
-                    draw_random_text(font, text, image);
+                    draw_random_text(&font.font, text, image, rng, &mut placer, visible)?
                 }
-                PlacementStyle::Repeated { times, direction } => {
-                    info!("Appending repeated text for image {image:p} with {times} times and {direction} direction");
-                    apply_repeated_text(font, text, image, times, &direction);
+                PlacementStyle::Repeated(repetition) => {
+                    let Repetition { times, direction } = &repetition;
+                    info!(
+                        "Appending repeated text for image {image:p} with {times} times and {direction} direction"
+                    );
+                    let mut details = apply_repeated_text(
+                        &font.font,
+                        text,
+                        image,
+                        &repetition,
+                        rng,
+                        &mut placer,
+                        visible,
+                    )?;
+                    details["times"] = json!(times);
+                    details["direction"] = json!(direction.to_string());
+                    details
                 }
-            }
+            };
+
+            details["text"] = json!(text);
+            details["template"] = json!(phrase.template());
+            details["font"] = json!(font.name);
+            drawn.push(details);
         }
+
+        ctx.record(self, json!({ "texts": drawn }));
+
+        Ok(())
     }
 }
@@ -0,0 +1,429 @@
+use crate::weight::{default_weight, valid_weights};
+use rand::Rng;
+use rand::seq::IndexedRandom;
+use serde::Deserialize;
+use std::path::Path;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum PhraseError {
+    #[error("Failed to read phrases: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Invalid TOML phrases: {0}")]
+    Toml(#[from] toml::de::Error),
+
+    #[error("Invalid JSON phrases: {0}")]
+    Json(#[from] serde_json::Error),
+
+    #[error("Unsupported phrases format: {0}")]
+    UnsupportedFormat(String),
+
+    #[error("Unknown placeholder {{{0}}} in {1:?}")]
+    UnknownPlaceholder(String, String),
+
+    #[error("Unclosed placeholder in {0:?}")]
+    UnclosedPlaceholder(String),
+
+    #[error("{0:?} uses {{name}} but no names are given")]
+    MissingNames(String),
+
+    #[error("No phrases given")]
+    Empty,
+
+    #[error("Phrase weights must be non-negative and not all zero")]
+    InvalidWeights,
+}
+
+/// Part of a phrase, placeholders are expanded every time the phrase is drawn.
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Literal(String),
+    /// `{time}`, a random time of the day, e.g. `3:33 AM`.
+    Time,
+    /// `{date}`, a random date between 1990 and 2009, e.g. `07/14/1997`.
+    Date,
+    /// `{counter}`, starts at a random number and goes up by one on every use in the same image.
+    Counter,
+    /// `{name}`, one of the names of the list.
+    Name,
+}
+
+#[derive(Debug, Clone)]
+pub struct Phrase {
+    template: String,
+    segments: Vec<Segment>,
+    weight: f32,
+}
+
+impl Phrase {
+    /// Parses a template, `{{` and `}}` stand for literal braces.
+    pub fn new(template: impl Into<String>, weight: f32) -> Result<Self, PhraseError> {
+        let template = template.into();
+        let mut segments = Vec::new();
+        let mut literal = String::new();
+        let mut chars = template.chars();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.as_str().starts_with('{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.as_str().starts_with('}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let rest = chars.as_str();
+                    let end = rest
+                        .find('}')
+                        .ok_or_else(|| PhraseError::UnclosedPlaceholder(template.clone()))?;
+
+                    let segment = match &rest[..end] {
+                        "time" => Segment::Time,
+                        "date" => Segment::Date,
+                        "counter" => Segment::Counter,
+                        "name" => Segment::Name,
+                        other => {
+                            return Err(PhraseError::UnknownPlaceholder(
+                                other.to_string(),
+                                template.clone(),
+                            ));
+                        }
+                    };
+
+                    if !literal.is_empty() {
+                        segments.push(Segment::Literal(std::mem::take(&mut literal)));
+                    }
+                    segments.push(segment);
+                    chars = rest[end + 1..].chars();
+                }
+                c => literal.push(c),
+            }
+        }
+
+        if !literal.is_empty() {
+            segments.push(Segment::Literal(literal));
+        }
+
+        Ok(Self {
+            template,
+            segments,
+            weight,
+        })
+    }
+
+    pub fn template(&self) -> &str {
+        &self.template
+    }
+
+    pub fn weight(&self) -> f32 {
+        self.weight
+    }
+
+    fn uses_names(&self) -> bool {
+        self.segments.contains(&Segment::Name)
+    }
+}
+
+/// State shared by the phrases drawn on the same image.
+pub struct RenderState {
+    counter: u32,
+}
+
+impl RenderState {
+    pub fn new(rng: &mut impl Rng) -> Self {
+        Self {
+            counter: rng.random_range(1..1000),
+        }
+    }
+}
+
+/// Weighted phrases drawn by the text transformation, with the names `{name}` picks from.
+///
+/// Phrase files are either plain text with one phrase per line, or TOML/JSON:
+///
+/// ```toml
+/// names = ["Alice", "Mara"]
+/// phrases = [
+///     "Exit?",
+///     { text = "{name}, it is {time}", weight = 3.0 },
+///     { text = "Day {counter}" },
+/// ]
+/// ```
+#[derive(Debug, Clone)]
+pub struct Phrases {
+    phrases: Vec<Phrase>,
+    names: Vec<String>,
+}
+
+/// A phrase as written in a file or a config, either just the text or with a weight.
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub(crate) enum PhraseEntry {
+    Text(String),
+    Weighted {
+        text: String,
+        #[serde(default = "default_weight")]
+        weight: f32,
+    },
+}
+
+#[derive(Deserialize)]
+pub(crate) struct PhraseFile {
+    #[serde(default)]
+    pub(crate) phrases: Vec<PhraseEntry>,
+    #[serde(default)]
+    pub(crate) names: Vec<String>,
+}
+
+impl PhraseFile {
+    pub(crate) fn load(path: impl AsRef<Path>) -> Result<Self, PhraseError> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)?;
+
+        let file = match path.extension().and_then(|e| e.to_str()) {
+            Some("txt") => PhraseFile {
+                phrases: contents
+                    .lines()
+                    .map(str::trim)
+                    .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                    .map(|line| PhraseEntry::Text(line.to_string()))
+                    .collect(),
+                names: Vec::new(),
+            },
+            Some("toml") => toml::from_str(&contents)?,
+            Some("json") => serde_json::from_str(&contents)?,
+            other => {
+                return Err(PhraseError::UnsupportedFormat(
+                    other.unwrap_or_default().to_string(),
+                ));
+            }
+        };
+
+        Ok(file)
+    }
+}
+
+impl Phrases {
+    pub fn new(phrases: Vec<Phrase>, names: Vec<String>) -> Result<Self, PhraseError> {
+        if phrases.is_empty() {
+            return Err(PhraseError::Empty);
+        }
+
+        if !valid_weights(phrases.iter().map(|p| p.weight)) {
+            return Err(PhraseError::InvalidWeights);
+        }
+
+        if names.is_empty()
+            && let Some(phrase) = phrases.iter().find(|p| p.uses_names())
+        {
+            return Err(PhraseError::MissingNames(phrase.template.clone()));
+        }
+
+        Ok(Self { phrases, names })
+    }
+
+    /// Loads phrases from a `.txt`, `.toml` or `.json` file, picking the format by its extension.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, PhraseError> {
+        Self::from_file(PhraseFile::load(path)?)
+    }
+
+    pub(crate) fn from_file(file: PhraseFile) -> Result<Self, PhraseError> {
+        let phrases = file
+            .phrases
+            .into_iter()
+            .map(|entry| match entry {
+                PhraseEntry::Text(text) => Phrase::new(text, default_weight()),
+                PhraseEntry::Weighted { text, weight } => Phrase::new(text, weight),
+            })
+            .collect::<Result<_, _>>()?;
+
+        Self::new(phrases, file.names)
+    }
+
+    pub fn phrases(&self) -> &[Phrase] {
+        &self.phrases
+    }
+
+    /// Picks a phrase according to the weights.
+    pub fn choose(&self, rng: &mut impl Rng) -> Option<&Phrase> {
+        self.phrases.choose_weighted(rng, |p| p.weight).ok()
+    }
+
+    /// Expands the placeholders of `phrase`.
+    pub fn render(&self, phrase: &Phrase, state: &mut RenderState, rng: &mut impl Rng) -> String {
+        let mut text = String::new();
+
+        for segment in &phrase.segments {
+            match segment {
+                Segment::Literal(literal) => text.push_str(literal),
+                Segment::Time => {
+                    let hour = rng.random_range(1..=12);
+                    let minute = rng.random_range(0..60);
+                    let period = if rng.random_bool(0.5) { "AM" } else { "PM" };
+                    text.push_str(&format!("{hour}:{minute:02} {period}"));
+                }
+                Segment::Date => {
+                    let year = rng.random_range(1990..=2009);
+                    let month = rng.random_range(1..=12);
+                    let day = rng.random_range(1..=28);
+                    text.push_str(&format!("{month:02}/{day:02}/{year}"));
+                }
+                Segment::Counter => {
+                    text.push_str(&state.counter.to_string());
+                    state.counter += 1;
+                }
+                Segment::Name => {
+                    if let Some(name) = self.names.choose(rng) {
+                        text.push_str(name);
+                    }
+                }
+            }
+        }
+
+        text
+    }
+}
+
+impl Default for Phrases {
+    fn default() -> Self {
+        let phrases = [
+            "Why do you keep coming back?",
+            "The walls remember you.",
+            "I dreamt of you last night.",
+            "Why do you always return?",
+            "Exit?",
+            "WAKE UP",
+            "This is a Dream",
+            "come with me, dear",
+            "i want to go back",
+            "he is watching!",
+            "It's time to go home",
+            "It's funny!",
+        ];
+
+        Self {
+            phrases: phrases
+                .into_iter()
+                .map(|text| Phrase {
+                    template: text.to_string(),
+                    segments: vec![Segment::Literal(text.to_string())],
+                    weight: default_weight(),
+                })
+                .collect(),
+            names: Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    fn render(phrases: &Phrases, state: &mut RenderState) -> String {
+        let mut rng = StdRng::seed_from_u64(0);
+        phrases.render(&phrases.phrases[0], state, &mut rng)
+    }
+
+    #[test]
+    fn doubled_braces_are_literal() {
+        let phrase = Phrase::new("{{not a placeholder}} }} {", 1.0);
+
+        // A lone `{` at the end is an unclosed placeholder
+        assert!(matches!(phrase, Err(PhraseError::UnclosedPlaceholder(_))));
+
+        let phrase = Phrase::new("{{not a placeholder}} }}", 1.0).unwrap();
+        assert_eq!(
+            phrase.segments,
+            [Segment::Literal("{not a placeholder} }".into())]
+        );
+    }
+
+    #[test]
+    fn placeholders_split_the_literals() {
+        let phrase = Phrase::new("Day {counter}, {time} on {date}", 1.0).unwrap();
+
+        assert_eq!(
+            phrase.segments,
+            [
+                Segment::Literal("Day ".into()),
+                Segment::Counter,
+                Segment::Literal(", ".into()),
+                Segment::Time,
+                Segment::Literal(" on ".into()),
+                Segment::Date,
+            ]
+        );
+    }
+
+    #[test]
+    fn unclosed_placeholders_are_rejected() {
+        let err = Phrase::new("Day {counter", 1.0).unwrap_err();
+
+        assert!(
+            matches!(err, PhraseError::UnclosedPlaceholder(template) if template == "Day {counter")
+        );
+    }
+
+    #[test]
+    fn unknown_placeholders_are_rejected() {
+        let err = Phrase::new("Hello {who}", 1.0).unwrap_err();
+
+        let PhraseError::UnknownPlaceholder(name, template) = err else {
+            panic!("expected an unknown placeholder, got {err:?}");
+        };
+        assert_eq!(name, "who");
+        assert_eq!(template, "Hello {who}");
+    }
+
+    #[test]
+    fn counter_goes_up_on_every_use() {
+        let phrases = Phrases::new(
+            vec![Phrase::new("{counter} {counter}", 1.0).unwrap()],
+            vec![],
+        )
+        .unwrap();
+        let mut state = RenderState { counter: 41 };
+
+        assert_eq!(render(&phrases, &mut state), "41 42");
+        assert_eq!(render(&phrases, &mut state), "43 44");
+    }
+
+    #[test]
+    fn names_are_required_by_name_placeholders() {
+        let phrase = Phrase::new("{name}, wake up", 1.0).unwrap();
+
+        let err = Phrases::new(vec![phrase.clone()], vec![]).unwrap_err();
+        assert!(
+            matches!(err, PhraseError::MissingNames(template) if template == "{name}, wake up")
+        );
+
+        let phrases = Phrases::new(vec![phrase], vec!["Mara".into()]).unwrap();
+        assert_eq!(
+            render(&phrases, &mut RenderState { counter: 1 }),
+            "Mara, wake up"
+        );
+    }
+
+    #[test]
+    fn weights_must_allow_a_choice() {
+        let phrase = |weight| Phrase::new("Exit?", weight).unwrap();
+
+        assert!(matches!(
+            Phrases::new(vec![], vec![]),
+            Err(PhraseError::Empty)
+        ));
+        for weights in [vec![0.0, 0.0], vec![1.0, -1.0], vec![f32::NAN]] {
+            let phrases = weights.into_iter().map(phrase).collect();
+            assert!(matches!(
+                Phrases::new(phrases, vec![]),
+                Err(PhraseError::InvalidWeights)
+            ));
+        }
+        assert!(Phrases::new(vec![phrase(0.0), phrase(2.0)], vec![]).is_ok());
+    }
+}
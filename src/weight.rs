@@ -0,0 +1,21 @@
+//! Shared by everything picked by weight: phrases, assets, Pinterest queries and `one_of` steps.
+
+/// Weight of an entry that doesn't set one.
+pub fn default_weight<W: From<f32>>() -> W {
+    W::from(1.0)
+}
+
+/// Whether a choice can be made by these weights: all finite and non-negative, and not all zero.
+pub fn valid_weights<W: Into<f64>>(weights: impl IntoIterator<Item = W>) -> bool {
+    let mut any_positive = false;
+
+    for weight in weights {
+        let weight = weight.into();
+        if !weight.is_finite() || weight < 0.0 {
+            return false;
+        }
+        any_positive |= weight > 0.0;
+    }
+
+    any_positive
+}
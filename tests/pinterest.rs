@@ -0,0 +1,703 @@
+//! Runs `PinterestProvider` against a local mock server answering with the recorded
+//! `BaseSearchResource` responses in `tests/fixtures/pinterest`, whose `{{base}}` placeholders
+//! are replaced with the address of the mock server.
+
+use dreamcore_image_processor::provider::cache::{BackgroundCache, CachedProvider};
+use dreamcore_image_processor::provider::pinterest::filter::PinterestFilter;
+use dreamcore_image_processor::provider::pinterest::session::PinterestSession;
+use dreamcore_image_processor::provider::pinterest::state::PinterestState;
+use dreamcore_image_processor::provider::pinterest::{PinterestProvider, PinterestQuery};
+use dreamcore_image_processor::provider::prefetch::{PrefetchOptions, PrefetchProvider};
+use dreamcore_image_processor::provider::retry::{RetryPolicy, RetryProvider};
+use dreamcore_image_processor::provider::{
+    Background, BackgroundProvider, ErrorClass, FetchBackgroundError,
+};
+use image::{DynamicImage, ImageFormat, RgbImage};
+use reqwest::StatusCode;
+use serde_json::Value;
+use std::collections::HashSet;
+use std::io::Cursor;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use wiremock::matchers::{body_string_contains, method, path, path_regex};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+const SEARCH_PATH: &str = "/resource/BaseSearchResource/get/";
+const CSRF_TOKEN: &str = "test-token";
+
+fn fixture(server: &MockServer, name: &str) -> Value {
+    let path = format!(
+        "{}/tests/fixtures/pinterest/{name}",
+        env!("CARGO_MANIFEST_DIR")
+    );
+    let contents = std::fs::read_to_string(&path).expect("fixture exists");
+
+    serde_json::from_str(&contents.replace("{{base}}", &server.uri())).expect("fixture is JSON")
+}
+
+fn png() -> Vec<u8> {
+    let mut bytes = Vec::new();
+    DynamicImage::ImageRgb8(RgbImage::from_pixel(32, 24, [120, 160, 180].into()))
+        .write_to(&mut Cursor::new(&mut bytes), ImageFormat::Png)
+        .unwrap();
+    bytes
+}
+
+fn session(server: &MockServer) -> PinterestSession {
+    PinterestSession {
+        base_url: server.uri(),
+        csrf_token: Some(CSRF_TOKEN.into()),
+        ..Default::default()
+    }
+}
+
+fn provider(server: &MockServer) -> PinterestProvider {
+    PinterestProvider::new("liminal pool").with_session(session(server))
+}
+
+fn original(server: &MockServer, pin: u32, extension: &str) -> String {
+    format!("{}/images/originals/{pin}.{extension}", server.uri())
+}
+
+async fn mount_search(server: &MockServer, fixture_name: &str) {
+    Mock::given(method("POST"))
+        .and(path(SEARCH_PATH))
+        .respond_with(ResponseTemplate::new(200).set_body_json(fixture(server, fixture_name)))
+        .mount(server)
+        .await;
+}
+
+async fn mount_images(server: &MockServer) {
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/images/originals/\d+\.(jpg|png)$"))
+        .respond_with(ResponseTemplate::new(200).set_body_raw(png(), "image/png"))
+        .mount(server)
+        .await;
+}
+
+async fn search_requests(server: &MockServer) -> Vec<wiremock::Request> {
+    server
+        .received_requests()
+        .await
+        .unwrap()
+        .into_iter()
+        .filter(|request| request.url.path() == SEARCH_PATH)
+        .collect()
+}
+
+/// The form-encoded search options, decoded back into JSON.
+fn search_options(request: &wiremock::Request) -> Value {
+    let body = String::from_utf8_lossy(&request.body);
+    let data = body
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| *key == "data")
+        .map(|(_, value)| {
+            urlencoding::decode(&value.replace('+', " "))
+                .unwrap()
+                .into_owned()
+        })
+        .expect("search request has data");
+
+    serde_json::from_str::<Value>(&data).unwrap()["options"].clone()
+}
+
+/// Answers with the first page, the second one for its bookmark and no results after that.
+/// Returns the bookmark of the first page.
+async fn mount_pages(server: &MockServer) -> String {
+    let page_1 = fixture(server, "search_page_1.json");
+    let bookmark = page_1["resource_response"]["bookmark"]
+        .as_str()
+        .unwrap()
+        .to_string();
+
+    Mock::given(method("POST"))
+        .and(path(SEARCH_PATH))
+        .and(body_string_contains(&bookmark[..16]))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(fixture(server, "search_page_2.json")),
+        )
+        .mount(server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path(SEARCH_PATH))
+        .and(body_string_contains("-end-"))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(fixture(server, "search_empty.json")),
+        )
+        .mount(server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path(SEARCH_PATH))
+        .respond_with(ResponseTemplate::new(200).set_body_json(page_1))
+        .mount(server)
+        .await;
+
+    bookmark
+}
+
+#[tokio::test]
+async fn follows_bookmarks_across_pages() {
+    let server = MockServer::start().await;
+    let bookmark = mount_pages(&server).await;
+    mount_images(&server).await;
+
+    let provider = provider(&server);
+    let mut sources = HashSet::new();
+    for _ in 0..4 {
+        let Background { source, .. } = provider.fetch_background().await.unwrap();
+        sources.insert(source);
+    }
+
+    let expected = [1, 2]
+        .map(|pin| original(&server, pin, "jpg"))
+        .into_iter()
+        .chain([3, 4].map(|pin| original(&server, pin, "jpg")))
+        .collect::<HashSet<_>>();
+    assert_eq!(sources, expected);
+
+    assert!(matches!(
+        provider.fetch_background().await,
+        Err(FetchBackgroundError::NoImages)
+    ));
+
+    let requests = search_requests(&server).await;
+    assert_eq!(
+        search_options(&requests[0])["bookmarks"],
+        serde_json::json!([])
+    );
+    assert_eq!(
+        search_options(&requests[1])["bookmarks"],
+        serde_json::json!([bookmark])
+    );
+    assert_eq!(search_options(&requests[0])["query"], "liminal pool");
+
+    let headers = &requests[0].headers;
+    assert_eq!(headers["x-csrftoken"], CSRF_TOKEN);
+    assert!(
+        headers["cookie"]
+            .to_str()
+            .unwrap()
+            .contains(&format!("csrftoken={CSRF_TOKEN}"))
+    );
+    assert_eq!(
+        headers["x-pinterest-source-url"],
+        "/search/pins/?q=liminal%20pool"
+    );
+}
+
+#[tokio::test]
+async fn empty_results_mean_no_images() {
+    let server = MockServer::start().await;
+    mount_search(&server, "search_empty.json").await;
+
+    let err = provider(&server).fetch_background().await.unwrap_err();
+
+    assert!(matches!(err, FetchBackgroundError::NoImages));
+    assert_eq!(err.class(), ErrorClass::Fatal);
+}
+
+#[tokio::test]
+async fn malformed_json_is_a_decode_error() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path(SEARCH_PATH))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_raw("<html>Log in to see more</html>", "text/html"),
+        )
+        .mount(&server)
+        .await;
+
+    let err = provider(&server).fetch_background().await.unwrap_err();
+
+    assert!(matches!(&err, FetchBackgroundError::NetworkError(err) if err.is_decode()));
+    assert_eq!(err.class(), ErrorClass::Skip);
+}
+
+#[tokio::test]
+async fn unexpected_json_shape_has_no_images() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path(SEARCH_PATH))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "resource_response": { "data": { "pins": [] } }
+        })))
+        .mount(&server)
+        .await;
+
+    let err = provider(&server).fetch_background().await.unwrap_err();
+
+    assert!(matches!(err, FetchBackgroundError::NoImages));
+}
+
+#[tokio::test]
+async fn skips_results_without_images_and_rejects_non_images() {
+    let server = MockServer::start().await;
+    mount_search(&server, "search_non_images.json").await;
+    mount_images(&server).await;
+    Mock::given(method("GET"))
+        .and(path("/images/originals/6.html"))
+        .respond_with(ResponseTemplate::new(200).set_body_raw("<html></html>", "text/html"))
+        .mount(&server)
+        .await;
+
+    let provider = provider(&server);
+
+    let background = provider.fetch_background().await.unwrap();
+    assert_eq!(background.source, original(&server, 7, "jpg"));
+
+    let err = provider.fetch_background().await.unwrap_err();
+    assert!(matches!(err, FetchBackgroundError::InvalidImage(_)));
+    assert_eq!(err.class(), ErrorClass::Skip);
+
+    // The story has no image, so the pool is empty now
+    assert!(matches!(
+        provider.fetch_background().await,
+        Err(FetchBackgroundError::NoImages)
+    ));
+    let requested = server.received_requests().await.unwrap();
+    assert!(requested.iter().all(|r| !r.url.path().contains("/5.")));
+}
+
+#[tokio::test]
+async fn rate_limits_are_transient_and_carry_retry_after() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path(SEARCH_PATH))
+        .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "7"))
+        .mount(&server)
+        .await;
+
+    let err = provider(&server).fetch_background().await.unwrap_err();
+
+    assert!(matches!(
+        err,
+        FetchBackgroundError::Http {
+            status: StatusCode::TOO_MANY_REQUESTS,
+            ..
+        }
+    ));
+    assert_eq!(err.class(), ErrorClass::Transient);
+    assert_eq!(err.retry_after(), Some(Duration::from_secs(7)));
+}
+
+#[tokio::test]
+async fn server_errors_are_transient() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path(SEARCH_PATH))
+        .respond_with(ResponseTemplate::new(503))
+        .mount(&server)
+        .await;
+
+    let err = provider(&server).fetch_background().await.unwrap_err();
+
+    assert_eq!(err.class(), ErrorClass::Transient);
+    assert_eq!(err.retry_after(), None);
+}
+
+#[tokio::test]
+async fn dead_image_urls_are_skipped() {
+    let server = MockServer::start().await;
+    mount_search(&server, "search_page_2.json").await;
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/images/originals/"))
+        .respond_with(ResponseTemplate::new(404))
+        .mount(&server)
+        .await;
+
+    let err = provider(&server).fetch_background().await.unwrap_err();
+
+    assert!(matches!(
+        err,
+        FetchBackgroundError::Http {
+            status: StatusCode::NOT_FOUND,
+            ..
+        }
+    ));
+    assert_eq!(err.class(), ErrorClass::Skip);
+}
+
+#[tokio::test]
+async fn rejected_session_bootstraps_a_new_csrf_token() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/"))
+        .respond_with(
+            ResponseTemplate::new(200).insert_header("Set-Cookie", "csrftoken=first; Path=/"),
+        )
+        .up_to_n_times(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .insert_header("Set-Cookie", "csrftoken=second; Path=/; Secure"),
+        )
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path(SEARCH_PATH))
+        .respond_with(ResponseTemplate::new(403))
+        .up_to_n_times(1)
+        .mount(&server)
+        .await;
+    mount_search(&server, "search_page_2.json").await;
+    mount_images(&server).await;
+
+    let session = PinterestSession {
+        csrf_token: None,
+        ..session(&server)
+    };
+    let provider = PinterestProvider::new("liminal pool").with_session(session);
+
+    let err = provider.fetch_background().await.unwrap_err();
+    assert!(matches!(
+        err,
+        FetchBackgroundError::Http {
+            status: StatusCode::FORBIDDEN,
+            ..
+        }
+    ));
+
+    provider.fetch_background().await.unwrap();
+
+    let tokens = search_requests(&server)
+        .await
+        .iter()
+        .map(|r| r.headers["x-csrftoken"].to_str().unwrap().to_string())
+        .collect::<Vec<_>>();
+    assert_eq!(tokens[..2], ["first", "second"]);
+}
+
+#[tokio::test]
+async fn retry_provider_honors_retry_after() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path(SEARCH_PATH))
+        .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "1"))
+        .up_to_n_times(1)
+        .mount(&server)
+        .await;
+    mount_search(&server, "search_page_2.json").await;
+    mount_images(&server).await;
+
+    let policy = RetryPolicy {
+        base_delay: Duration::from_millis(1),
+        ..Default::default()
+    };
+    let provider = RetryProvider::new(provider(&server), policy);
+
+    let start = Instant::now();
+    provider.fetch_background().await.unwrap();
+
+    assert!(start.elapsed() >= Duration::from_secs(1));
+}
+
+#[tokio::test]
+async fn filters_results_before_downloading() {
+    let server = MockServer::start().await;
+    mount_search(&server, "search_mixed.json").await;
+    mount_images(&server).await;
+
+    let filter = PinterestFilter {
+        min_width: 800,
+        min_height: 600,
+        aspect_ratio: Some(0.5..=2.0),
+        extensions: vec!["jpg".into(), ".PNG".into()],
+        ..Default::default()
+    };
+    let provider = provider(&server).with_filter(filter);
+
+    let background = provider.fetch_background().await.unwrap();
+    assert_eq!(background.source, original(&server, 13, "png"));
+    assert!(matches!(
+        provider.fetch_background().await,
+        Err(FetchBackgroundError::NoImages)
+    ));
+
+    let downloads = server
+        .received_requests()
+        .await
+        .unwrap()
+        .into_iter()
+        .filter(|request| request.url.path().starts_with("/images/"))
+        .map(|request| request.url.path().to_string())
+        .collect::<Vec<_>>();
+    assert_eq!(downloads, ["/images/originals/13.png"]);
+}
+
+#[tokio::test]
+async fn rotates_queries_with_separate_bookmarks() {
+    let server = MockServer::start().await;
+    for (query, fixture_name) in [
+        ("liminal pool", "search_page_2.json"),
+        ("weirdcore hallway", "search_page_1.json"),
+    ] {
+        Mock::given(method("POST"))
+            .and(path(SEARCH_PATH))
+            .and(move |request: &wiremock::Request| search_options(request)["query"] == query)
+            .respond_with(ResponseTemplate::new(200).set_body_json(fixture(&server, fixture_name)))
+            .mount(&server)
+            .await;
+    }
+    mount_images(&server).await;
+
+    let provider = PinterestProvider::with_queries([
+        PinterestQuery::new("liminal pool", 1.0),
+        PinterestQuery::new("weirdcore hallway", 2.0),
+        PinterestQuery::new("dreamcore mall", 0.0),
+    ])
+    .unwrap()
+    .with_session(session(&server));
+
+    let mut sources = HashSet::new();
+    while let Ok(background) = provider.fetch_background().await {
+        sources.insert(background.source);
+    }
+
+    let expected = [1, 2, 3, 4]
+        .map(|pin| original(&server, pin, "jpg"))
+        .into_iter()
+        .collect::<HashSet<_>>();
+    assert_eq!(sources, expected);
+
+    let options = search_requests(&server)
+        .await
+        .iter()
+        .map(search_options)
+        .collect::<Vec<_>>();
+    let bookmarks = |query: &str| {
+        options
+            .iter()
+            .filter(|options| options["query"] == query)
+            .map(|options| options["bookmarks"].clone())
+            .collect::<Vec<_>>()
+    };
+
+    let page_1 = fixture(&server, "search_page_1.json");
+    let liminal = bookmarks("liminal pool");
+    let weirdcore = bookmarks("weirdcore hallway");
+    assert_eq!(liminal[0], serde_json::json!([]));
+    assert_eq!(liminal[1], serde_json::json!(["-end-"]));
+    assert_eq!(weirdcore[0], serde_json::json!([]));
+    assert_eq!(
+        weirdcore[1],
+        serde_json::json!([page_1["resource_response"]["bookmark"]])
+    );
+    assert!(bookmarks("dreamcore mall").is_empty());
+}
+
+#[test]
+fn rejects_invalid_query_weights() {
+    assert!(PinterestProvider::with_queries([]).is_err());
+    assert!(PinterestProvider::with_queries([PinterestQuery::new("liminal pool", 0.0)]).is_err());
+    assert!(
+        PinterestProvider::with_queries([PinterestQuery::new("liminal pool", f32::NAN)]).is_err()
+    );
+}
+
+#[tokio::test]
+async fn checkpoints_and_resumes_pagination() {
+    let server = MockServer::start().await;
+    mount_pages(&server).await;
+    mount_images(&server).await;
+
+    let state = std::env::temp_dir().join(format!("pinterest-state-{}.json", std::process::id()));
+    let _ = std::fs::remove_file(&state);
+
+    let first_run = provider(&server).with_checkpoint(&state);
+    let first = first_run.fetch_background().await.unwrap().source;
+    first_run.mark_used(&first).await;
+    let unused = first_run.fetch_background().await.unwrap().source;
+    drop(first_run);
+
+    let saved = PinterestState::load(&state)
+        .unwrap()
+        .expect("state is saved");
+    assert_eq!(saved.queries.len(), 1);
+    assert_eq!(saved.queries[0].query, "liminal pool");
+    assert_eq!(saved.queries[0].bookmark.as_deref(), Some("-end-"));
+    assert_eq!(saved.queries[0].images.len(), 3);
+    assert!(!saved.queries[0].images.contains(&first));
+    // Fetched but never used, so it's the next one to fetch
+    assert_eq!(saved.queries[0].images.last(), Some(&unused));
+
+    let second_run = provider(&server).with_checkpoint(&state);
+    let mut sources = HashSet::from([first]);
+    while let Ok(background) = second_run.fetch_background().await {
+        assert!(sources.insert(background.source));
+    }
+    assert_eq!(sources.len(), 4);
+
+    let requests = search_requests(&server).await;
+    // The second run starts with the bookmark the first one stopped at
+    assert!(requests.len() > 2);
+    assert_eq!(
+        search_options(&requests[2])["bookmarks"],
+        serde_json::json!(["-end-"])
+    );
+
+    std::fs::remove_file(&state).unwrap();
+}
+
+#[tokio::test]
+async fn broken_state_starts_fresh() {
+    let server = MockServer::start().await;
+    mount_pages(&server).await;
+    mount_images(&server).await;
+
+    let state = std::env::temp_dir().join(format!("pinterest-broken-{}.json", std::process::id()));
+    std::fs::write(&state, "{\"queries\": [").unwrap();
+
+    let provider = provider(&server).with_checkpoint(&state);
+    provider.fetch_background().await.unwrap();
+    drop(provider);
+
+    let requests = search_requests(&server).await;
+    assert_eq!(
+        search_options(&requests[0])["bookmarks"],
+        serde_json::json!([])
+    );
+    // The broken file is replaced with the state of this run
+    assert!(PinterestState::load(&state).unwrap().is_some());
+
+    std::fs::remove_file(&state).unwrap();
+}
+
+#[tokio::test]
+async fn retry_after_is_capped_by_the_policy() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path(SEARCH_PATH))
+        .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "86400"))
+        .up_to_n_times(1)
+        .mount(&server)
+        .await;
+    mount_search(&server, "search_page_2.json").await;
+    mount_images(&server).await;
+
+    let policy = RetryPolicy {
+        max_delay: Duration::from_millis(10),
+        ..Default::default()
+    };
+    let provider = RetryProvider::new(provider(&server), policy);
+
+    tokio::time::timeout(Duration::from_secs(5), provider.fetch_background())
+        .await
+        .expect("the retry waits at most max_delay")
+        .unwrap();
+}
+
+#[tokio::test]
+async fn prefetching_stops_on_fatal_errors() {
+    let server = MockServer::start().await;
+    // The landing page sets no CSRF cookie, so there is no session to search with
+    Mock::given(method("GET"))
+        .and(path("/"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&server)
+        .await;
+
+    let session = PinterestSession {
+        csrf_token: None,
+        ..session(&server)
+    };
+    let options = PrefetchOptions {
+        workers: 2,
+        retry_delay: Duration::from_millis(1),
+        ..Default::default()
+    };
+    let provider = PrefetchProvider::new(
+        PinterestProvider::new("liminal pool").with_session(session),
+        options,
+    );
+
+    let err = provider.fetch_background().await.unwrap_err();
+    assert!(matches!(err, FetchBackgroundError::Session(_)));
+    assert_eq!(err.class(), ErrorClass::Fatal);
+
+    let rest = tokio::time::timeout(Duration::from_secs(5), async {
+        while let Err(err) = provider.fetch_background().await {
+            if matches!(err, FetchBackgroundError::NoImages) {
+                return;
+            }
+        }
+    });
+    rest.await.expect("the workers stop");
+
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    let bootstraps = server.received_requests().await.unwrap().len();
+    assert!(bootstraps <= 2, "{bootstraps} bootstrap requests");
+}
+
+#[tokio::test]
+async fn prefetching_stops_at_the_limit() {
+    let server = MockServer::start().await;
+    mount_pages(&server).await;
+    mount_images(&server).await;
+
+    let options = PrefetchOptions {
+        limit: Some(2),
+        ..Default::default()
+    };
+    let provider = PrefetchProvider::new(provider(&server), options);
+
+    provider.fetch_background().await.unwrap();
+    provider.fetch_background().await.unwrap();
+    assert!(matches!(
+        provider.fetch_background().await,
+        Err(FetchBackgroundError::NoImages)
+    ));
+
+    let downloads = server
+        .received_requests()
+        .await
+        .unwrap()
+        .into_iter()
+        .filter(|request| request.url.path().starts_with("/images/"))
+        .count();
+    assert_eq!(downloads, 2);
+}
+
+#[tokio::test]
+async fn caches_the_downloaded_files_unchanged() {
+    let server = MockServer::start().await;
+    mount_search(&server, "search_page_1.json").await;
+
+    let mut jpeg = Vec::new();
+    DynamicImage::ImageRgb8(RgbImage::from_pixel(32, 24, [90, 60, 30].into()))
+        .write_to(&mut Cursor::new(&mut jpeg), ImageFormat::Jpeg)
+        .unwrap();
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/images/originals/\d+\.(jpg|png)$"))
+        .respond_with(ResponseTemplate::new(200).set_body_raw(jpeg.clone(), "image/jpeg"))
+        .mount(&server)
+        .await;
+
+    let dir = std::env::temp_dir().join(format!("pinterest-cache-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+
+    let cache = Arc::new(BackgroundCache::open(&dir, u64::MAX).unwrap());
+    let provider = CachedProvider::new(provider(&server), cache.clone());
+    let source = provider.fetch_background().await.unwrap().source;
+    drop(provider);
+    drop(cache);
+
+    let cached = std::fs::read_dir(&dir)
+        .unwrap()
+        .map(|file| file.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "jpg"))
+        .map(|path| std::fs::read(path).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(cached, [jpeg.clone()]);
+
+    // The index is saved when the cache is dropped
+    let cache = BackgroundCache::open(&dir, u64::MAX).unwrap();
+    let background = cache.get(&source).await.expect("background is cached");
+    assert_eq!(background.encoded.as_deref(), Some(&jpeg[..]));
+
+    drop(cache);
+    std::fs::remove_dir_all(&dir).unwrap();
+}